@@ -1,5 +1,7 @@
 use bytemuck;
 
+use crate::globals::FogUniform;
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct BackgroundUniform {
@@ -8,6 +10,12 @@ pub struct BackgroundUniform {
     pub pitch: f32,
     pub yaw: f32,
     pub fovy: f32,
+    pub crosshair: u32,
+    pub _pad0: u32,
+    // points at the sun
+    pub sun_dir: [f32; 3],
+    pub _pad1: u32,
+    pub fog: FogUniform,
 }
 
 pub fn background(device: &wgpu::Device) -> (wgpu::Buffer, wgpu::BindGroupLayout, wgpu::BindGroup) {
@@ -5,6 +5,9 @@ struct Globals {
     pitch: f32,
     yaw: f32,
     fovy: f32,
+    crosshair: u32,
+    sun_dir: vec3<f32>,
+    fog: Fog,
 };
 
 @group(0) @binding(0)
@@ -47,6 +50,25 @@ fn twinkle(seed: f32, time: f32) -> f32 {
     return 0.5 + 0.5 * sin(time * freq + phase);
 }
 
+// Blue gradient during the day, reddened towards the sun around sunrise
+// and sunset, and the starfield once the sun is well below the horizon.
+fn sky(dir: vec3<f32>, sun: vec3<f32>) -> vec3<f32> {
+    let up = clamp(dir.y, 0.0, 1.0);
+    let day = smoothstep(-0.15, 0.1, sun.y);
+    let dusk = 1.0 - smoothstep(0.0, 0.35, abs(sun.y));
+
+    let zenith = vec3<f32>(0.25, 0.45, 0.85);
+    let horizon = vec3<f32>(0.7, 0.82, 0.95);
+    var color = mix(horizon, zenith, sqrt(up)) * day;
+
+    let toward_sun = max(dot(dir, sun), 0.0);
+    let glow = pow(1.0 - up, 4.0) * mix(0.3, 1.0, toward_sun) * dusk;
+    color = mix(color, vec3<f32>(1.0, 0.45, 0.2), glow);
+
+    let night = vec3<f32>(0.004, 0.006, 0.018);
+    return max(color, night);
+}
+
 @fragment
 fn fg_main(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
         let time = f32(globals.millis_elapsed) * 0.0005;
@@ -62,6 +84,9 @@ fn fg_main(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
 
     dir = rotate_x(dir, globals.pitch);
     dir = rotate_y(dir, globals.yaw);
+    // the camera looks down -z in world space
+    let world_dir = dir * vec3<f32>(1.0, 1.0, -1.0);
+    let sun = normalize(globals.sun_dir);
 
     let chunk = floor(dir * 100.0);
 
@@ -71,6 +96,27 @@ fn fg_main(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
         1.0,
         hash3(chunk)
     ) * flicker;
+    let starlight = 1.0 - smoothstep(-0.2, 0.0, sun.y);
 
-    return vec4<f32>(vec3<f32>(stars), 1.0);
+    let disc = dot(world_dir, sun);
+    let sun_disc = smoothstep(0.9990, 0.9995, disc) * vec3<f32>(1.0, 0.95, 0.8);
+    let moon_disc = smoothstep(0.9993, 0.9996, -disc) * vec3<f32>(0.8, 0.85, 0.9);
+
+    var color = sky(world_dir, sun) + stars * starlight + sun_disc + moon_disc;
+    // the sky is infinitely far away, a long ray is close enough
+    let fog = fog_amount(globals.fog, world_dir, 1e4);
+    color = mix(color, fog_color(globals.fog, world_dir), fog);
+    return vec4<f32>(color, 1.0);
+}
+
+// Drawn over the world at the end of the main pass, so it shows no matter
+// what is under it
+@fragment
+fn fg_crosshair(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
+    let d = abs(frag_coord.xy - vec2<f32>(globals.resolution) * 0.5);
+    let on_crosshair = (d.x < 1.0 && d.y < 10.0) || (d.y < 1.0 && d.x < 10.0);
+    if globals.crosshair == 0 || !on_crosshair {
+        discard;
+    }
+    return vec4<f32>(0.9, 0.9, 0.9, 1.0);
 }
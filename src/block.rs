@@ -0,0 +1,126 @@
+pub type BlockId = u16;
+
+pub const MAX_BLOCK_TYPES: usize = 256;
+
+pub const AIR: BlockId = 0;
+pub const STONE: BlockId = 1;
+pub const DIRT: BlockId = 2;
+pub const GRASS: BlockId = 3;
+pub const SAND: BlockId = 4;
+pub const SNOW: BlockId = 5;
+pub const WOOD: BlockId = 6;
+pub const LEAVES: BlockId = 7;
+pub const BRICK: BlockId = 8;
+
+// Ids the registry can hand out, the only ones chunks store
+pub fn is_valid(id: BlockId) -> bool {
+    (id as usize) < MAX_BLOCK_TYPES
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockType {
+    pub name: String,
+    pub color: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BlockColorsUniform {
+    pub colors: [[f32; 4]; MAX_BLOCK_TYPES],
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockRegistry {
+    types: Vec<BlockType>,
+    version: u64,
+}
+
+impl BlockRegistry {
+    pub fn empty() -> Self {
+        Self {
+            types: Vec::new(),
+            version: 0,
+        }
+    }
+
+    pub fn new() -> Self {
+        let mut registry = Self::empty();
+        registry.register("air", [0.0, 0.0, 0.0, 0.0]);
+        registry.register("stone", [0.5, 0.5, 0.52, 1.0]);
+        registry.register("dirt", [0.45, 0.3, 0.18, 1.0]);
+        registry.register("grass", [0.3, 0.6, 0.2, 1.0]);
+        registry.register("sand", [0.86, 0.8, 0.55, 1.0]);
+        registry.register("snow", [0.95, 0.97, 1.0, 1.0]);
+        registry.register("wood", [0.5, 0.35, 0.2, 1.0]);
+        registry.register("leaves", [0.2, 0.45, 0.15, 1.0]);
+        registry.register("brick", [0.65, 0.25, 0.2, 1.0]);
+        registry
+    }
+
+    pub fn register(&mut self, name: &str, color: [f32; 4]) -> Option<BlockId> {
+        if self.types.len() >= MAX_BLOCK_TYPES {
+            return None;
+        }
+        self.types.push(BlockType {
+            name: name.to_string(),
+            color,
+        });
+        self.version += 1;
+        Some((self.types.len() - 1) as BlockId)
+    }
+
+    pub fn get(&self, id: BlockId) -> Option<&BlockType> {
+        self.types.get(id as usize)
+    }
+
+    pub fn find(&self, name: &str) -> Option<BlockId> {
+        self.types
+            .iter()
+            .position(|t| t.name == name)
+            .map(|i| i as BlockId)
+    }
+
+    // Closest solid block type by colour
+    pub fn closest(&self, color: [f32; 4]) -> BlockId {
+        let distance =
+            |t: &BlockType| -> f32 { (0..3).map(|i| (t.color[i] - color[i]).powi(2)).sum() };
+        self.types
+            .iter()
+            .enumerate()
+            .skip(1)
+            .min_by(|(_, a), (_, b)| distance(a).total_cmp(&distance(b)))
+            .map_or(AIR, |(i, _)| i as BlockId)
+    }
+
+    // Changes whenever a block type is added, so uploads can be skipped
+    // while it stays the same.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &BlockType> {
+        self.types.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.types.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.types.is_empty()
+    }
+
+    pub fn colors(&self) -> BlockColorsUniform {
+        let mut colors = [[1.0, 0.0, 1.0, 1.0]; MAX_BLOCK_TYPES];
+        for (color, t) in colors.iter_mut().zip(self.types.iter()) {
+            *color = t.color;
+        }
+        BlockColorsUniform { colors }
+    }
+}
+
+impl Default for BlockRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use image::{imageops, RgbaImage};
+
+use crate::block::{BlockId, BlockRegistry, MAX_BLOCK_TYPES};
+
+pub const DEFAULT_TEXTURE_SIZE: u32 = 16;
+
+pub const FACE_TOP: usize = 0;
+pub const FACE_SIDE: usize = 1;
+pub const FACE_BOTTOM: usize = 2;
+
+// Texture layer of the top, side and bottom faces of every block type,
+// padded to 16 bytes per entry for the uniform buffer.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BlockFacesUniform {
+    pub layers: [[u32; 4]; MAX_BLOCK_TYPES],
+}
+
+// Square textures for block faces, all of the same power of two size so
+// they fit into one texture array. Layer 0 is plain white and faces using
+// it are drawn in the flat block colour.
+pub struct BlockTextures {
+    size: u32,
+    layers: Vec<RgbaImage>,
+    faces: Vec<[u32; 3]>,
+}
+
+impl BlockTextures {
+    pub fn new(size: u32) -> Self {
+        let size = size.max(1).next_power_of_two();
+        Self {
+            size,
+            layers: vec![RgbaImage::from_pixel(size, size, image::Rgba([255; 4]))],
+            faces: vec![[0; 3]; MAX_BLOCK_TYPES],
+        }
+    }
+
+    // Looks up textures for every block type by name: `<name>_top.png`,
+    // `<name>_side.png` and `<name>_bottom.png`, each falling back to
+    // `<name>.png`. Block types without any file keep their flat colour.
+    pub fn load_dir(
+        dir: impl AsRef<Path>,
+        registry: &BlockRegistry,
+        size: u32,
+    ) -> Result<Self, image::ImageError> {
+        let dir = dir.as_ref();
+        let mut textures = Self::new(size);
+        let mut loaded: HashMap<PathBuf, u32> = HashMap::new();
+
+        for (id, block_type) in registry.iter().enumerate().skip(1) {
+            let mut faces = [0; 3];
+            for (face, suffix) in [
+                (FACE_TOP, "_top"),
+                (FACE_SIDE, "_side"),
+                (FACE_BOTTOM, "_bottom"),
+            ] {
+                let path = [
+                    format!("{}{suffix}.png", block_type.name),
+                    format!("{}.png", block_type.name),
+                ]
+                .into_iter()
+                .map(|file| dir.join(file))
+                .find(|path| path.is_file());
+                let Some(path) = path else {
+                    continue;
+                };
+                faces[face] = match loaded.get(&path) {
+                    Some(&layer) => layer,
+                    None => {
+                        let layer = textures.add_layer(&image::open(&path)?.into_rgba8());
+                        loaded.insert(path, layer);
+                        layer
+                    }
+                };
+            }
+            textures.faces[id] = faces;
+        }
+        log::info!(
+            "Loaded {} block textures from {}",
+            textures.layers.len() - 1,
+            dir.display()
+        );
+        Ok(textures)
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn layer_count(&self) -> u32 {
+        self.layers.len() as u32
+    }
+
+    pub fn mip_level_count(&self) -> u32 {
+        self.size.ilog2() + 1
+    }
+
+    // Adds a texture, scaled to the common size, and returns its layer
+    pub fn add_layer(&mut self, image: &RgbaImage) -> u32 {
+        let image = if image.dimensions() == (self.size, self.size) {
+            image.clone()
+        } else {
+            imageops::resize(image, self.size, self.size, imageops::FilterType::Nearest)
+        };
+        self.layers.push(image);
+        self.layers.len() as u32 - 1
+    }
+
+    // Splits an atlas into square tiles, row by row, and returns the layer
+    // of the first one. Partial tiles at the right and bottom are skipped.
+    pub fn add_atlas(&mut self, atlas: &RgbaImage, tile_size: u32) -> std::ops::Range<u32> {
+        let first = self.layer_count();
+        let tile_size = tile_size.max(1);
+        for y in 0..atlas.height() / tile_size {
+            for x in 0..atlas.width() / tile_size {
+                let tile =
+                    imageops::crop_imm(atlas, x * tile_size, y * tile_size, tile_size, tile_size);
+                self.add_layer(&tile.to_image());
+            }
+        }
+        first..self.layer_count()
+    }
+
+    pub fn set_faces(&mut self, block: BlockId, top: u32, side: u32, bottom: u32) {
+        if let Some(faces) = self.faces.get_mut(block as usize) {
+            *faces = [top, side, bottom];
+        }
+    }
+
+    pub fn faces(&self, block: BlockId) -> [u32; 3] {
+        self.faces.get(block as usize).copied().unwrap_or([0; 3])
+    }
+
+    pub fn faces_uniform(&self) -> BlockFacesUniform {
+        let mut layers = [[0; 4]; MAX_BLOCK_TYPES];
+        for (layer, faces) in layers.iter_mut().zip(&self.faces) {
+            let max = self.layer_count() - 1;
+            *layer = [faces[0].min(max), faces[1].min(max), faces[2].min(max), 0];
+        }
+        BlockFacesUniform { layers }
+    }
+
+    // Every mip level of a layer, from full size down to 1x1
+    pub fn mips(&self, layer: u32) -> Vec<RgbaImage> {
+        let mut mips = vec![self.layers[layer as usize].clone()];
+        for level in 1..self.mip_level_count() {
+            let size = self.size >> level;
+            let mip = imageops::resize(
+                mips.last().unwrap(),
+                size,
+                size,
+                imageops::FilterType::Triangle,
+            );
+            mips.push(mip);
+        }
+        mips
+    }
+}
+
+impl Default for BlockTextures {
+    fn default() -> Self {
+        Self::new(DEFAULT_TEXTURE_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block;
+    use crate::save::tests::TempDir;
+
+    fn solid(size: u32, color: [u8; 3]) -> RgbaImage {
+        let [r, g, b] = color;
+        RgbaImage::from_pixel(size, size, image::Rgba([r, g, b, 255]))
+    }
+
+    fn color(textures: &BlockTextures, layer: u32) -> [u8; 4] {
+        textures.layers[layer as usize].get_pixel(0, 0).0
+    }
+
+    #[test]
+    fn load_dir_falls_back_to_the_plain_name_and_shares_layers() {
+        let dir = TempDir::new("block_textures");
+        std::fs::create_dir_all(&dir.0).unwrap();
+        solid(8, [10, 20, 30])
+            .save(dir.0.join("stone.png"))
+            .unwrap();
+        solid(16, [200, 0, 0])
+            .save(dir.0.join("grass_top.png"))
+            .unwrap();
+        solid(16, [0, 200, 0])
+            .save(dir.0.join("grass.png"))
+            .unwrap();
+
+        let textures = BlockTextures::load_dir(&dir.0, &block::BlockRegistry::new(), 16).unwrap();
+        // stone once, grass_top and grass once each
+        assert_eq!(textures.layer_count(), 4);
+        let [top, side, bottom] = textures.faces(block::GRASS);
+        assert_eq!(color(&textures, top), [200, 0, 0, 255]);
+        assert_eq!(color(&textures, side), [0, 200, 0, 255]);
+        assert_eq!(side, bottom);
+
+        let [top, side, bottom] = textures.faces(block::STONE);
+        assert!(top == side && side == bottom);
+        assert_eq!(textures.layers[top as usize].dimensions(), (16, 16));
+        assert_eq!(color(&textures, top), [10, 20, 30, 255]);
+
+        assert_eq!(textures.faces(block::DIRT), [0; 3]);
+    }
+
+    #[test]
+    fn atlas_tiles_are_added_row_by_row() {
+        let mut textures = BlockTextures::new(4);
+        // three whole tiles across and two down, plus partial ones
+        let atlas = RgbaImage::from_fn(3 * 4 + 2, 2 * 4 + 3, |x, y| {
+            image::Rgba([(x / 4) as u8, (y / 4) as u8, 0, 255])
+        });
+        let layers = textures.add_atlas(&atlas, 4);
+        assert_eq!(layers, 1..7);
+        for (i, layer) in layers.enumerate() {
+            let (x, y) = (i as u8 % 3, i as u8 / 3);
+            assert_eq!(color(&textures, layer), [x, y, 0, 255]);
+        }
+    }
+
+    #[test]
+    fn faces_uniform_clamps_missing_layers() {
+        let mut textures = BlockTextures::new(16);
+        let layer = textures.add_layer(&solid(16, [1, 2, 3]));
+        textures.set_faces(block::STONE, layer, 99, 0);
+        // ids beyond the table are ignored
+        textures.set_faces(MAX_BLOCK_TYPES as BlockId, 1, 1, 1);
+
+        let uniform = textures.faces_uniform();
+        assert_eq!(uniform.layers[block::STONE as usize], [layer, layer, 0, 0]);
+        assert_eq!(uniform.layers[block::DIRT as usize], [0; 4]);
+        assert_eq!(textures.faces(MAX_BLOCK_TYPES as BlockId), [0; 3]);
+    }
+
+    #[test]
+    fn mips_go_down_to_one_texel() {
+        // sizes are rounded up to a power of two
+        for (size, levels) in [(20, 6), (8, 4), (1, 1), (0, 1)] {
+            let textures = BlockTextures::new(size);
+            assert_eq!(textures.mip_level_count(), levels);
+            let mips = textures.mips(0);
+            assert_eq!(mips.len(), levels as usize);
+            for (level, mip) in mips.iter().enumerate() {
+                let expected = textures.size() >> level;
+                assert_eq!(mip.dimensions(), (expected, expected));
+            }
+            assert_eq!(mips.last().unwrap().dimensions(), (1, 1));
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use crate::{chunk::CHUNK_SIZE, chunk_storage::WindowSize};
+
+pub const BRICK_SIZE: usize = 8;
+pub const CHUNK_BRICKS: usize = CHUNK_SIZE / BRICK_SIZE;
+
+// 1 for every brick of a chunk that is not all air, as x, then z, then y
+pub type ChunkBricks = [u8; CHUNK_BRICKS * CHUNK_BRICKS * CHUNK_BRICKS];
+
+// Coarse level above the voxels of the render window: one flag per brick
+// of 8³ voxels telling whether there is anything in it. Chunks wrap around
+// the same way they do in the chunk texture, so the brick texture is a
+// copy of this and both are updated chunk by chunk.
+#[derive(Debug, Clone)]
+pub struct Brickmap {
+    // bricks along x, y and z
+    dims: [usize; 3],
+    bricks: Vec<u8>,
+    // occupied bricks in every layer across x, y and z, in texture order
+    layer_counts: [Vec<u32>; 3],
+}
+
+impl Brickmap {
+    pub fn new(size: WindowSize) -> Self {
+        let xz = size.xz as usize * CHUNK_BRICKS;
+        let y = size.y as usize * CHUNK_BRICKS;
+        Self {
+            dims: [xz, y, xz],
+            bricks: vec![0; xz * xz * y],
+            layer_counts: [vec![0; xz], vec![0; y], vec![0; xz]],
+        }
+    }
+
+    // Copies the bricks of a chunk whose voxels start at `slot`, the texel
+    // of the chunk texture as (x, z, y).
+    pub fn set_chunk(&mut self, slot: [u32; 3], bricks: &ChunkBricks) {
+        let [sx, sz, sy] = slot.map(|v| v as usize / BRICK_SIZE);
+        for y in 0..CHUNK_BRICKS {
+            for z in 0..CHUNK_BRICKS {
+                for x in 0..CHUNK_BRICKS {
+                    let p = [sx + x, sy + y, sz + z];
+                    let i = self.index(p);
+                    let old = self.bricks[i] != 0;
+                    self.bricks[i] = bricks[x + z * CHUNK_BRICKS + y * CHUNK_BRICKS * CHUNK_BRICKS];
+                    let new = self.bricks[i] != 0;
+                    if old != new {
+                        for (counts, layer) in self.layer_counts.iter_mut().zip(p) {
+                            if new {
+                                counts[layer] += 1;
+                            } else {
+                                counts[layer] -= 1;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Whether each layer of bricks across `axis` (0 is x, 1 y and 2 z)
+    // holds anything, starting at the window's low side. `wrap` is the
+    // texel holding the window's first voxel as (x, y, z).
+    pub fn occupied_layers(&self, axis: usize, wrap: [u32; 3]) -> Vec<bool> {
+        let counts = &self.layer_counts[axis];
+        let first = wrap[axis] as usize / BRICK_SIZE;
+        (0..counts.len())
+            .map(|i| counts[(first + i) % counts.len()] != 0)
+            .collect()
+    }
+
+    // Bricks along x, z and y like the brick texture
+    pub fn texture_size(&self) -> [u32; 3] {
+        [self.dims[0], self.dims[2], self.dims[1]].map(|v| v as u32)
+    }
+
+    fn index(&self, [x, y, z]: [usize; 3]) -> usize {
+        x + z * self.dims[0] + y * self.dims[0] * self.dims[2]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn occupied_layers_follow_the_wrap() {
+        let mut bricks = Brickmap::new(WindowSize::new(2, 1));
+        let mut chunk: ChunkBricks = [0; CHUNK_BRICKS * CHUNK_BRICKS * CHUNK_BRICKS];
+        // brick (1, 2, 3) of the chunk in the second slot along x
+        chunk[1 + 3 * CHUNK_BRICKS + 2 * CHUNK_BRICKS * CHUNK_BRICKS] = 1;
+        let slot = [CHUNK_SIZE as u32, 0, 0];
+        bricks.set_chunk(slot, &chunk);
+
+        let only =
+            |len: usize, layer: usize| -> Vec<bool> { (0..len).map(|i| i == layer).collect() };
+        let len = 2 * CHUNK_BRICKS;
+        assert_eq!(
+            bricks.occupied_layers(0, [0, 0, 0]),
+            only(len, CHUNK_BRICKS + 1)
+        );
+        assert_eq!(bricks.occupied_layers(1, [0, 0, 0]), only(CHUNK_BRICKS, 2));
+        assert_eq!(bricks.occupied_layers(2, [0, 0, 0]), only(len, 3));
+
+        // the window starting in the second slot puts it at the front
+        let wrap = [
+            CHUNK_SIZE as u32,
+            BRICK_SIZE as u32,
+            CHUNK_SIZE as u32 + 2 * BRICK_SIZE as u32,
+        ];
+        assert_eq!(bricks.occupied_layers(0, wrap), only(len, 1));
+        assert_eq!(bricks.occupied_layers(1, wrap), only(CHUNK_BRICKS, 1));
+        assert_eq!(bricks.occupied_layers(2, wrap), only(len, CHUNK_BRICKS + 1));
+
+        // a second brick in the same layer keeps it occupied until both go
+        let mut two = chunk;
+        two[1 + 3 * CHUNK_BRICKS] = 1;
+        bricks.set_chunk(slot, &two);
+        bricks.set_chunk(slot, &chunk);
+        assert_eq!(bricks.occupied_layers(0, wrap), only(len, 1));
+        bricks.set_chunk(slot, &[0; CHUNK_BRICKS * CHUNK_BRICKS * CHUNK_BRICKS]);
+        assert!(!bricks.occupied_layers(0, wrap).contains(&true));
+        assert!(!bricks.occupied_layers(1, wrap).contains(&true));
+    }
+}
@@ -2,14 +2,16 @@ use std::time;
 
 use cgmath::*;
 
+// Matrix4::new takes columns, so the z remap sits in the last column
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
     0.0, 1.0, 0.0, 0.0,
-    0.0, 0.0, 0.5, 0.5,
-    0.0, 0.0, 0.0, 1.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
 );
 
+#[derive(Debug, Clone)]
 pub struct Camera {
     pub position: Point3<f32>,
     pub pitch: Rad<f32>,
@@ -174,3 +176,24 @@ impl CameraController {
         (self.mouse_dx, self.mouse_dy) = (0.0, 0.0);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depth_runs_from_zero_at_near_to_one_at_far() {
+        let mut camera = Camera::new(1.5);
+        camera.position = Point3::new(3.0, 4.0, 5.0);
+        camera.yaw = Rad(0.7);
+        camera.pitch = Rad(0.3);
+        let mat = camera.proj_view_matrix();
+        let depth = |d: f32| {
+            let p = camera.position + camera.direction() * d;
+            mat.transform_point(p).z
+        };
+        assert!(depth(camera.znear).abs() < 1e-4);
+        assert!((depth(camera.zfar) - 1.0).abs() < 1e-4);
+        assert!((0.0..1.0).contains(&depth(10.0)));
+    }
+}
@@ -1,49 +1,346 @@
+use crate::block::{self, BlockId};
 use crate::lattice;
-use noise::{NoiseFn, OpenSimplex, Seedable};
+use std::io::{self, Read, Write};
 
 pub const CHUNK_SIZE: usize = 32;
+pub const CHUNK_VOLUME: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
 
-#[repr(C)]
-#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+// Voxels are stored as indices into a per-chunk palette, bit-packed into u64
+// words. A chunk made of a single block type needs no index data at all.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Chunk {
-    pub blocks: [[u32; CHUNK_SIZE]; CHUNK_SIZE],
+    palette: Vec<BlockId>,
+    bits: u32,
+    data: Vec<u64>,
+}
+
+const fn index(x: usize, y: usize, z: usize) -> usize {
+    (x * CHUNK_SIZE + z) * CHUNK_SIZE + y
+}
+
+// Palettes never hold more than `MAX_BLOCK_TYPES` ids, so 8 bits are enough
+const fn bits_for(palette_len: usize) -> u32 {
+    match palette_len {
+        0..=1 => 0,
+        2 => 1,
+        3..=4 => 2,
+        5..=16 => 4,
+        _ => 8,
+    }
 }
 
 impl Chunk {
-    pub const fn empty() -> Self {
+    pub fn empty() -> Self {
+        Self::filled(block::AIR)
+    }
+
+    // Invalid block ids give an empty chunk
+    pub fn filled(block: BlockId) -> Self {
+        let block = if block::is_valid(block) {
+            block
+        } else {
+            log::warn!("Ignoring invalid block id {block}");
+            block::AIR
+        };
         Self {
-            blocks: [[0; CHUNK_SIZE]; CHUNK_SIZE],
+            palette: vec![block],
+            bits: 0,
+            data: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> BlockId {
+        if self.bits == 0 {
+            return self.palette[0];
         }
+        self.palette[self.palette_index(index(x, y, z))]
     }
 
-    pub fn noise(chunk_x: i32, chunk_y: i32, chunk_z: i32) -> Self {
-        const FREQUENCY: f64 = 0.04;
-        let noise = OpenSimplex::new(2);
-        let mut result = Self::empty();
+    // Invalid block ids leave the voxel as it is
+    pub fn set(&mut self, x: usize, y: usize, z: usize, block: BlockId) {
+        if !block::is_valid(block) {
+            log::warn!("Ignoring invalid block id {block}");
+            return;
+        }
+        let p = match self.palette.iter().position(|&b| b == block) {
+            Some(p) => p,
+            None => {
+                self.palette.push(block);
+                let bits = bits_for(self.palette.len());
+                if bits != self.bits {
+                    self.repack(bits);
+                }
+                self.palette.len() - 1
+            }
+        };
+        if self.bits != 0 {
+            self.set_palette_index(index(x, y, z), p);
+        }
+    }
+
+    pub fn palette(&self) -> &[BlockId] {
+        &self.palette
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.palette.iter().all(|&b| b == block::AIR)
+    }
+
+    // Y occupancy of a single column, bit `y` set for every non-air voxel
+    pub fn column(&self, x: usize, z: usize) -> u32 {
+        if self.bits == 0 {
+            return if self.palette[0] == block::AIR {
+                0
+            } else {
+                u32::MAX
+            };
+        }
+        let mut mask = 0;
+        for y in 0..CHUNK_SIZE {
+            mask |= ((self.get(x, y, z) != block::AIR) as u32) << y;
+        }
+        mask
+    }
+
+    // Drops palette entries that are no longer referenced and shrinks the
+    // index width accordingly.
+    pub fn compact(&mut self) {
+        if self.bits == 0 {
+            return;
+        }
+        let mut used = vec![false; self.palette.len()];
+        for i in 0..CHUNK_VOLUME {
+            used[self.palette_index(i)] = true;
+        }
+        if used.iter().all(|&u| u) {
+            return;
+        }
+
+        let mut remap = vec![0; self.palette.len()];
+        let mut palette = Vec::new();
+        for (p, &block) in self.palette.iter().enumerate() {
+            if used[p] {
+                remap[p] = palette.len();
+                palette.push(block);
+            }
+        }
+
+        let indices: Vec<usize> = (0..CHUNK_VOLUME)
+            .map(|i| remap[self.palette_index(i)])
+            .collect();
+        self.palette = palette;
+        self.bits = bits_for(self.palette.len());
+        self.data = vec![0; CHUNK_VOLUME * self.bits as usize / 64];
+        if self.bits != 0 {
+            for (i, p) in indices.into_iter().enumerate() {
+                self.set_palette_index(i, p);
+            }
+        }
+    }
+
+    // Palette length, palette, index width and index words, little endian.
+    pub fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+        w.write_all(&(self.palette.len() as u32).to_le_bytes())?;
+        for block in &self.palette {
+            w.write_all(&block.to_le_bytes())?;
+        }
+        w.write_all(&[self.bits as u8])?;
+        for word in &self.data {
+            w.write_all(&word.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from(r: &mut impl Read) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        let mut u32_buf = [0; 4];
+        r.read_exact(&mut u32_buf)?;
+        let palette_len = u32::from_le_bytes(u32_buf) as usize;
+        if palette_len == 0 || palette_len > block::MAX_BLOCK_TYPES {
+            return Err(invalid("invalid chunk palette length"));
+        }
+        let mut palette = Vec::with_capacity(palette_len);
+        for _ in 0..palette_len {
+            let mut id = [0; 2];
+            r.read_exact(&mut id)?;
+            let id = BlockId::from_le_bytes(id);
+            if !block::is_valid(id) {
+                return Err(invalid("invalid block id in chunk palette"));
+            }
+            palette.push(id);
+        }
+
+        let mut bits = [0; 1];
+        r.read_exact(&mut bits)?;
+        let bits = bits[0] as u32;
+        if bits != bits_for(palette_len) {
+            return Err(invalid("chunk index width does not match its palette"));
+        }
+
+        let mut data = vec![0; CHUNK_VOLUME * bits as usize / 64];
+        for word in data.iter_mut() {
+            let mut buf = [0; 8];
+            r.read_exact(&mut buf)?;
+            *word = u64::from_le_bytes(buf);
+        }
+        let chunk = Self {
+            palette,
+            bits,
+            data,
+        };
+        if bits != 0 && (0..CHUNK_VOLUME).any(|i| chunk.palette_index(i) >= palette_len) {
+            return Err(invalid("chunk index out of palette range"));
+        }
+        Ok(chunk)
+    }
 
-        for i in 0..CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE {
-            let x = i / CHUNK_SIZE / CHUNK_SIZE;
-            let y = i % CHUNK_SIZE;
-            let z = i / CHUNK_SIZE % CHUNK_SIZE;
+    fn palette_index(&self, i: usize) -> usize {
+        read_index(&self.data, self.bits, i)
+    }
 
-            let world_x = CHUNK_SIZE as i32 * chunk_x + x as i32;
-            let world_y = CHUNK_SIZE as i32 * chunk_y + y as i32;
-            let world_z = CHUNK_SIZE as i32 * chunk_z + z as i32;
+    fn set_palette_index(&mut self, i: usize, p: usize) {
+        write_index(&mut self.data, self.bits, i, p);
+    }
 
-            let value = noise.get([
-                world_x as f64 * FREQUENCY,
-                world_y as f64 * FREQUENCY,
-                world_z as f64 * FREQUENCY,
-            ]);
-            let block = (value >= FREQUENCY) as u32;
-            result.blocks[x][z] |= block << y;
+    fn repack(&mut self, bits: u32) {
+        let old_bits = self.bits;
+        let old_data =
+            std::mem::replace(&mut self.data, vec![0; CHUNK_VOLUME * bits as usize / 64]);
+        self.bits = bits;
+        if old_bits == 0 {
+            return;
+        }
+        for i in 0..CHUNK_VOLUME {
+            self.set_palette_index(i, read_index(&old_data, old_bits, i));
         }
-        return result;
     }
 }
 
+fn read_index(data: &[u64], bits: u32, i: usize) -> usize {
+    let per_word = 64 / bits as usize;
+    let shift = (i % per_word) as u32 * bits;
+    let mask = (1u64 << bits) - 1;
+    ((data[i / per_word] >> shift) & mask) as usize
+}
+
+fn write_index(data: &mut [u64], bits: u32, i: usize, p: usize) {
+    let per_word = 64 / bits as usize;
+    let shift = (i % per_word) as u32 * bits;
+    let mask = (1u64 << bits) - 1;
+    let word = &mut data[i / per_word];
+    *word = (*word & !(mask << shift)) | ((p as u64 & mask) << shift);
+}
+
 impl Default for Chunk {
     fn default() -> Self {
         Self::empty()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(i: usize) -> (usize, usize, usize) {
+        (
+            i / (CHUNK_SIZE * CHUNK_SIZE),
+            i % CHUNK_SIZE,
+            i / CHUNK_SIZE % CHUNK_SIZE,
+        )
+    }
+
+    fn assert_matches(chunk: &Chunk, expected: &[BlockId]) {
+        for (i, &block) in expected.iter().enumerate() {
+            let (x, y, z) = pos(i);
+            assert_eq!(chunk.get(x, y, z), block, "voxel {:?}", (x, y, z));
+        }
+    }
+
+    #[test]
+    fn indices_widen_as_the_palette_grows() {
+        let mut chunk = Chunk::empty();
+        let mut expected = vec![block::AIR; CHUNK_VOLUME];
+        // palette length at which each width is first needed
+        let widths = [(2, 1), (3, 2), (5, 4), (17, 8)];
+        for id in 1..block::MAX_BLOCK_TYPES as BlockId {
+            // spread the blocks over the chunk so every word gets used
+            let i = id as usize * 7919 % CHUNK_VOLUME;
+            let (x, y, z) = pos(i);
+            chunk.set(x, y, z, id);
+            expected[i] = id;
+            if let Some(&(_, bits)) = widths.iter().find(|(len, _)| *len == id as usize + 1) {
+                assert_eq!(chunk.bits, bits);
+                assert_matches(&chunk, &expected);
+            }
+        }
+        assert_eq!(chunk.palette().len(), block::MAX_BLOCK_TYPES);
+        assert_eq!(chunk.bits, 8);
+        assert_matches(&chunk, &expected);
+
+        let mut bytes = Vec::new();
+        chunk.write_to(&mut bytes).unwrap();
+        assert_eq!(Chunk::read_from(&mut bytes.as_slice()).unwrap(), chunk);
+    }
+
+    #[test]
+    fn out_of_range_ids_are_rejected() {
+        let invalid = block::MAX_BLOCK_TYPES as BlockId + 44;
+        let mut chunk = Chunk::filled(block::STONE);
+        chunk.set(1, 2, 3, invalid);
+        assert_eq!(chunk, Chunk::filled(block::STONE));
+        assert_eq!(Chunk::filled(invalid), Chunk::empty());
+
+        // a saved palette holding the id does not load
+        let mut bytes = Vec::new();
+        let mut two = Chunk::empty();
+        two.set(0, 0, 0, block::STONE);
+        two.write_to(&mut bytes).unwrap();
+        bytes[6..8].copy_from_slice(&invalid.to_le_bytes());
+        let error = Chunk::read_from(&mut bytes.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn compact_drops_unused_blocks() {
+        let mut chunk = Chunk::empty();
+        for id in 1..20 {
+            chunk.set(id as usize, 0, 0, id);
+        }
+        assert_eq!(chunk.bits, 8);
+        for id in 3..20 {
+            chunk.set(id as usize, 0, 0, block::AIR);
+        }
+        chunk.compact();
+        assert_eq!(chunk.palette(), [block::AIR, 1, 2]);
+        assert_eq!(chunk.bits, 2);
+        assert_eq!(chunk.get(1, 0, 0), 1);
+        assert_eq!(chunk.get(2, 0, 0), 2);
+        assert_eq!(chunk.get(3, 0, 0), block::AIR);
+
+        // nothing but air left needs no indices at all
+        chunk.set(1, 0, 0, block::AIR);
+        chunk.set(2, 0, 0, block::AIR);
+        chunk.compact();
+        assert_eq!(chunk, Chunk::empty());
+    }
+
+    #[test]
+    fn filled_chunks_have_no_indices() {
+        let chunk = Chunk::filled(block::STONE);
+        assert_eq!(chunk.palette(), [block::STONE]);
+        assert!(chunk.data.is_empty());
+        assert_eq!(chunk.get(5, 31, 0), block::STONE);
+        assert_eq!(chunk.column(3, 4), u32::MAX);
+        assert!(!chunk.is_empty());
+
+        let empty = Chunk::empty();
+        assert!(empty.is_empty());
+        assert_eq!(empty.column(3, 4), 0);
+
+        // setting the same block again keeps it that way
+        let mut same = chunk.clone();
+        same.set(1, 2, 3, block::STONE);
+        assert_eq!(same, chunk);
+    }
+}
@@ -1,84 +1,770 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::block;
+use crate::brickmap::{self, ChunkBricks};
 use crate::chunk::{self, CHUNK_SIZE};
-use crate::lattice;
+use crate::chunk_worker::{ChunkSource, ChunkWorkers, ChunkWriter, SaveDone, SaveJob};
+use crate::generator::WorldGenerator;
+use crate::save::WorldSave;
+
+// Block ids fit in a byte as long as there are at most 256 block types
+type ChunkRenderBuffer = [u8; chunk::CHUNK_VOLUME];
+const _: () = assert!(block::MAX_BLOCK_TYPES <= 256);
+// One Y column occupancy word per x, z position
+type ChunkOccupancyBuffer = [u32; CHUNK_SIZE * CHUNK_SIZE];
+
+pub type ChunkPos = cgmath::Vector3<i32>;
+pub type BlockPos = cgmath::Vector3<i32>;
+
+// Chunks this far outside the render window stay loaded, so moving back
+// and forth across a chunk border does not reload them.
+const EVICT_MARGIN: i32 = 2;
+
+// World position of the first voxel of chunk (0, 0, 0). It stays put when
+// the window size changes so saved chunk coordinates remain valid.
+pub const WORLD_ORIGIN: BlockPos = cgmath::Vector3 {
+    x: -64,
+    y: -32,
+    z: -64,
+};
+
+// Splits a world voxel position into its chunk and the position inside it.
+pub fn chunk_of(pos: BlockPos) -> (ChunkPos, [usize; 3]) {
+    let v = pos - WORLD_ORIGIN;
+    let size = CHUNK_SIZE as i32;
+    (
+        ChunkPos::new(
+            v.x.div_euclid(size),
+            v.y.div_euclid(size),
+            v.z.div_euclid(size),
+        ),
+        [
+            v.x.rem_euclid(size) as usize,
+            v.y.rem_euclid(size) as usize,
+            v.z.rem_euclid(size) as usize,
+        ],
+    )
+}
+
+pub fn chunk_min(pos: ChunkPos) -> BlockPos {
+    pos * CHUNK_SIZE as i32 + WORLD_ORIGIN
+}
+
+// Size of the render window in chunks, square in x and z
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WindowSize {
+    pub xz: u32,
+    pub y: u32,
+}
+
+impl WindowSize {
+    pub const DEFAULT: Self = Self { xz: 4, y: 2 };
+    // 3D textures are at most 2048 texels on a side with the default limits
+    // devices are requested with
+    pub const MAX_TEXTURE_SIZE: u32 = 2048;
+    // Budget for the block id and occupancy textures together. The id
+    // texture dominates at a byte per voxel, the occupancy texture adds a bit.
+    pub const MAX_TEXTURE_BYTES: u64 = 1 << 28;
 
-pub const CHUNK_XZ: usize = lattice::XZ as usize / CHUNK_SIZE;
-pub const CHUNK_Y: usize = lattice::Y as usize / CHUNK_SIZE;
-pub const CHUNK_COUNT: usize = CHUNK_XZ * CHUNK_XZ * CHUNK_Y;
+    pub fn new(xz: u32, y: u32) -> Self {
+        Self {
+            xz: xz.max(1),
+            y: y.max(1),
+        }
+    }
+
+    // Fails if the chunk texture would be larger than `max_texture_size`
+    // texels on a side or take more than `MAX_TEXTURE_BYTES`.
+    pub fn check(&self, max_texture_size: u32) -> Result<(), String> {
+        let max_chunks = max_texture_size / CHUNK_SIZE as u32;
+        if self.xz > max_chunks || self.y > max_chunks {
+            return Err(format!(
+                "window size {}x{} is too large, the device allows at most {max_chunks} chunks on a side",
+                self.xz, self.y
+            ));
+        }
+        if self.texture_bytes() > Self::MAX_TEXTURE_BYTES {
+            return Err(format!(
+                "window size {}x{} is too large, its textures take {} MiB out of {} MiB",
+                self.xz,
+                self.y,
+                self.texture_bytes() >> 20,
+                Self::MAX_TEXTURE_BYTES >> 20
+            ));
+        }
+        Ok(())
+    }
 
-const CHUNK_BUFFER_SIZE: usize = (lattice::XZ * lattice::XZ * lattice::Y) as usize;
+    // Largest size no larger than this one that passes `check`, giving up
+    // width before height.
+    pub fn limit(&self, max_texture_size: u32) -> Self {
+        let max_chunks = (max_texture_size / CHUNK_SIZE as u32).max(1);
+        let mut size = Self::new(self.xz.min(max_chunks), self.y.min(max_chunks));
+        while size.texture_bytes() > Self::MAX_TEXTURE_BYTES && size.xz > 1 {
+            size.xz -= 1;
+        }
+        while size.texture_bytes() > Self::MAX_TEXTURE_BYTES && size.y > 1 {
+            size.y -= 1;
+        }
+        size
+    }
 
-type ChunkRenderBuffer = [u32; CHUNK_BUFFER_SIZE];
+    pub fn voxel_count(&self) -> u64 {
+        self.blocks_xz() as u64 * self.blocks_xz() as u64 * self.blocks_y() as u64
+    }
+
+    // Bytes of the block id texture, one per voxel, plus the occupancy
+    // texture, one bit per voxel
+    pub fn texture_bytes(&self) -> u64 {
+        self.voxel_count() + self.voxel_count() / 8
+    }
+
+    pub fn blocks_xz(&self) -> u32 {
+        self.xz * CHUNK_SIZE as u32
+    }
+
+    pub fn blocks_y(&self) -> u32 {
+        self.y * CHUNK_SIZE as u32
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        (self.xz * self.xz * self.y) as usize
+    }
+
+    // Origin of the render window that keeps `pos` in its centre chunk.
+    pub fn around(&self, pos: cgmath::Vector3<f32>) -> ChunkPos {
+        let (chunk, _) = chunk_of(pos.map(|v| v.floor() as i32));
+        chunk - ChunkPos::new(self.xz as i32 / 2, self.y as i32 / 2, self.xz as i32 / 2)
+    }
+
+    // Squared distance of a chunk's centre to the window's centre, doubled
+    // to stay in whole numbers.
+    pub fn centre_distance(&self, origin: ChunkPos, pos: ChunkPos) -> i32 {
+        let centre = origin * 2 + ChunkPos::new(self.xz as i32, self.y as i32, self.xz as i32);
+        let d = pos * 2 + ChunkPos::new(1, 1, 1) - centre;
+        d.x * d.x + d.y * d.y + d.z * d.z
+    }
+
+    pub fn chunks(&self, origin: ChunkPos) -> impl Iterator<Item = ChunkPos> {
+        let (xz, y) = (self.xz as i32, self.y as i32);
+        (0..xz).flat_map(move |x| {
+            (0..xz).flat_map(move |z| (0..y).map(move |y| origin + ChunkPos::new(x, y, z)))
+        })
+    }
+
+    pub fn contains(&self, origin: ChunkPos, pos: ChunkPos) -> bool {
+        let local = pos - origin;
+        (0..self.xz as i32).contains(&local.x)
+            && (0..self.y as i32).contains(&local.y)
+            && (0..self.xz as i32).contains(&local.z)
+    }
+
+    // Chunks wrap around inside the chunk texture, so moving the window only
+    // requires uploading the chunks that entered it. Returns the texel origin
+    // as (x, z, y) to match the texture layout.
+    pub fn texture_slot(&self, pos: ChunkPos) -> [u32; 3] {
+        let size = CHUNK_SIZE as u32;
+        [
+            pos.x.rem_euclid(self.xz as i32) as u32 * size,
+            pos.z.rem_euclid(self.xz as i32) as u32 * size,
+            pos.y.rem_euclid(self.y as i32) as u32 * size,
+        ]
+    }
+}
+
+impl Default for WindowSize {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+// Parses sizes like "6x2", in chunks
+impl std::str::FromStr for WindowSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (xz, y) = s
+            .split_once('x')
+            .ok_or_else(|| format!("expected <xz>x<y>, got {s:?}"))?;
+        let parse = |v: &str| {
+            v.trim()
+                .parse::<u32>()
+                .map_err(|e| format!("invalid window size {s:?}: {e}"))
+        };
+        let size = Self::new(parse(xz)?, parse(y)?);
+        size.check(Self::MAX_TEXTURE_SIZE)?;
+        Ok(size)
+    }
+}
 
 pub struct ChunkStorage {
-    chunks: [chunk::Chunk; CHUNK_COUNT],
+    chunks: HashMap<ChunkPos, chunk::Chunk>,
+    dirty: HashSet<ChunkPos>,
+    unsaved: HashSet<ChunkPos>,
+    block_types: block::BlockRegistry,
+    source: Arc<ChunkSource>,
+    workers: ChunkWorkers,
+    pending: HashSet<ChunkPos>,
+    window_size: WindowSize,
+    // only there for storages with a save directory
+    writer: Option<ChunkWriter>,
+    // chunks in saves that have not finished yet, with how many saves
+    saving: HashMap<ChunkPos, usize>,
+    saves_in_flight: usize,
 }
 
 impl ChunkStorage {
-    pub fn new() -> Self {
-        let mut chunks = [chunk::Chunk::default(); CHUNK_COUNT];
-        for x in 0..CHUNK_XZ {
-            for z in 0..CHUNK_XZ {
-                for y in 0..CHUNK_Y {
-                    chunks[z + x * CHUNK_XZ + y * CHUNK_XZ * CHUNK_XZ] =
-                        // chunk::Chunk::noise(x as i32, y as i32, z as i32);
-                        chunk::Chunk::empty();
-                    for cx in 0..CHUNK_SIZE {
-                        for cz in 0..CHUNK_SIZE {
-                            chunks[z + x * CHUNK_XZ + y * CHUNK_XZ * CHUNK_XZ].blocks[cx][cz] = 1;
-                        }
-                    }
-                    chunks[z + x * CHUNK_XZ + y * CHUNK_XZ * CHUNK_XZ].blocks[0][0] = u32::MAX;
+    pub fn new(generator: impl WorldGenerator + 'static) -> Self {
+        Self::with_source(
+            ChunkSource {
+                generator: Arc::new(generator),
+                save: None,
+            },
+            block::BlockRegistry::new(),
+        )
+    }
+
+    // Chunks stored in `dir` are loaded from there when they are needed,
+    // everything else comes from the generator.
+    pub fn open(
+        dir: impl AsRef<Path>,
+        generator: impl WorldGenerator + 'static,
+    ) -> io::Result<Self> {
+        let save = WorldSave::open(dir)?;
+        let block_types = save.load_block_types()?.unwrap_or_default();
+        Ok(Self::with_source(
+            ChunkSource {
+                generator: Arc::new(generator),
+                save: Some(save),
+            },
+            block_types,
+        ))
+    }
+
+    fn with_source(source: ChunkSource, block_types: block::BlockRegistry) -> Self {
+        let source = Arc::new(source);
+        let writer = source.save.clone().map(ChunkWriter::new);
+        Self {
+            chunks: HashMap::new(),
+            dirty: HashSet::new(),
+            unsaved: HashSet::new(),
+            block_types,
+            workers: ChunkWorkers::with_default_threads(source.clone()),
+            source,
+            pending: HashSet::new(),
+            window_size: WindowSize::DEFAULT,
+            writer,
+            saving: HashMap::new(),
+            saves_in_flight: 0,
+        }
+    }
+
+    // Size of the window around the camera that is loaded and rendered
+    pub fn window_size(&self) -> WindowSize {
+        self.window_size
+    }
+
+    pub fn set_window_size(&mut self, size: WindowSize) {
+        self.window_size = size;
+    }
+
+    pub fn generator(&self) -> &dyn WorldGenerator {
+        &*self.source.generator
+    }
+
+    pub fn save_dir(&self) -> Option<&Path> {
+        self.source.save.as_ref().map(|save| save.dir())
+    }
+
+    pub fn has_unsaved(&self) -> bool {
+        !self.unsaved.is_empty()
+    }
+
+    pub fn is_saving(&self) -> bool {
+        self.saves_in_flight > 0
+    }
+
+    // Hands a copy of every chunk modified since the last save to the
+    // writer thread and returns how many there were. The outcome is reported
+    // by `finished_saves`. Does nothing for storages without a save
+    // directory.
+    pub fn save_in_background(&mut self) -> usize {
+        let Some(writer) = &self.writer else {
+            return 0;
+        };
+        let chunks: Vec<_> = std::mem::take(&mut self.unsaved)
+            .into_iter()
+            .map(|pos| (pos, self.chunks.get(&pos).cloned()))
+            .collect();
+        for (pos, _) in &chunks {
+            *self.saving.entry(*pos).or_default() += 1;
+        }
+        self.saves_in_flight += 1;
+        let count = chunks.len();
+        writer.write(SaveJob {
+            block_types: self.block_types.clone(),
+            chunks,
+        });
+        count
+    }
+
+    // Results of the background saves that finished since the last call,
+    // with how many chunks each one wrote.
+    pub fn finished_saves(&mut self) -> Vec<io::Result<usize>> {
+        let mut results = Vec::new();
+        while let Some(done) = self.writer.as_ref().and_then(ChunkWriter::try_recv) {
+            results.push(self.finish_save(done));
+        }
+        results
+    }
+
+    // Saves every modified chunk and waits for all saves still in flight,
+    // for when the program is about to exit. Returns how many chunks were
+    // written.
+    pub fn save(&mut self) -> io::Result<usize> {
+        self.save_in_background();
+        let mut count = 0;
+        let mut error = None;
+        while self.saves_in_flight > 0 {
+            let Some(done) = self.writer.as_ref().and_then(ChunkWriter::recv) else {
+                break;
+            };
+            match self.finish_save(done) {
+                Ok(written) => count += written,
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        error.map_or(Ok(count), Err)
+    }
+
+    // Chunks of a failed save are written again by the next one.
+    fn finish_save(&mut self, done: SaveDone) -> io::Result<usize> {
+        self.saves_in_flight -= 1;
+        for pos in &done.chunks {
+            if let Some(count) = self.saving.get_mut(pos) {
+                *count -= 1;
+                if *count == 0 {
+                    self.saving.remove(pos);
                 }
             }
         }
+        match done.result {
+            Ok(()) => Ok(done.chunks.len()),
+            Err(e) => {
+                self.unsaved.extend(done.chunks);
+                Err(e)
+            }
+        }
+    }
+
+    pub fn block_types(&self) -> &block::BlockRegistry {
+        &self.block_types
+    }
+
+    pub fn block_types_mut(&mut self) -> &mut block::BlockRegistry {
+        &mut self.block_types
+    }
+
+    pub fn get(&self, x: i32, y: i32, z: i32) -> Option<&chunk::Chunk> {
+        self.chunks.get(&ChunkPos::new(x, y, z))
+    }
+
+    // Handing out a mutable chunk marks it as modified.
+    pub fn get_mut(&mut self, x: i32, y: i32, z: i32) -> Option<&mut chunk::Chunk> {
+        let pos = ChunkPos::new(x, y, z);
+        let chunk = self.chunks.get_mut(&pos)?;
+        self.dirty.insert(pos);
+        self.unsaved.insert(pos);
+        Some(chunk)
+    }
+
+    pub fn get_or_create(&mut self, x: i32, y: i32, z: i32) -> &mut chunk::Chunk {
+        let pos = ChunkPos::new(x, y, z);
+        self.unsaved.insert(pos);
+        self.load(pos)
+    }
+
+    fn load(&mut self, pos: ChunkPos) -> &mut chunk::Chunk {
+        self.dirty.insert(pos);
+        self.chunks
+            .entry(pos)
+            .or_insert_with(|| self.source.load(pos))
+    }
+
+    pub fn insert(&mut self, x: i32, y: i32, z: i32, chunk: chunk::Chunk) -> Option<chunk::Chunk> {
+        let pos = ChunkPos::new(x, y, z);
+        self.dirty.insert(pos);
+        self.unsaved.insert(pos);
+        self.chunks.insert(pos, chunk)
+    }
+
+    // Deletes the chunk from the world. The next save clears it from the
+    // save directory too, so it is generated again when loaded next.
+    pub fn remove(&mut self, x: i32, y: i32, z: i32) -> Option<chunk::Chunk> {
+        let pos = ChunkPos::new(x, y, z);
+        self.dirty.insert(pos);
+        self.unsaved.insert(pos);
+        self.chunks.remove(&pos)
+    }
+
+    pub fn mark_dirty(&mut self, x: i32, y: i32, z: i32) {
+        self.dirty.insert(ChunkPos::new(x, y, z));
+    }
+
+    pub fn is_dirty(&self, x: i32, y: i32, z: i32) -> bool {
+        self.dirty.contains(&ChunkPos::new(x, y, z))
+    }
+
+    // Returns every chunk modified since the last call.
+    pub fn take_dirty(&mut self) -> HashSet<ChunkPos> {
+        std::mem::take(&mut self.dirty)
+    }
+
+    pub fn is_loaded(&self, x: i32, y: i32, z: i32) -> bool {
+        self.chunks.contains_key(&ChunkPos::new(x, y, z))
+    }
+
+    pub fn loaded(&self) -> impl Iterator<Item = ChunkPos> + '_ {
+        self.chunks.keys().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    // Loads every chunk of the render window starting at the given chunk
+    // coordinates that is not loaded yet.
+    pub fn load_window(&mut self, x: i32, y: i32, z: i32) {
+        for pos in self.window_size.chunks(ChunkPos::new(x, y, z)) {
+            if !self.is_loaded(pos.x, pos.y, pos.z) {
+                self.load(pos);
+            }
+        }
+    }
 
-        Self { chunks }
+    // Queues a chunk for loading on the worker threads unless it is
+    // already loaded or on its way.
+    pub fn request(&mut self, x: i32, y: i32, z: i32) {
+        let pos = ChunkPos::new(x, y, z);
+        if self.chunks.contains_key(&pos) || !self.pending.insert(pos) {
+            return;
+        }
+        self.workers.request(pos);
     }
 
-    pub fn get(&self, x: isize, y: isize, z: isize) -> Option<&chunk::Chunk> {
-        if x < 0 || y < 0 || z < 0 {
-            return None;
+    // Requests every chunk of the window, closest to its centre first.
+    // Requests for chunks that left the window are cancelled and saved
+    // chunks further than `EVICT_MARGIN` outside of it are unloaded.
+    pub fn request_window(&mut self, x: i32, y: i32, z: i32) {
+        let origin = ChunkPos::new(x, y, z);
+        let size = self.window_size;
+        self.cancel_outside(origin);
+        self.evict_outside(origin);
+        let mut positions: Vec<ChunkPos> = size.chunks(origin).collect();
+        positions.sort_by_key(|&pos| size.centre_distance(origin, pos));
+        for pos in positions {
+            self.request(pos.x, pos.y, pos.z);
         }
-        let (x, y, z) = (x as usize, y as usize, z as usize);
-        self.chunks.get(x * CHUNK_XZ + z + y * CHUNK_XZ * CHUNK_XZ)
     }
 
-    pub fn copy_to_render_buffer(&self, x: isize, y: isize, z: isize) -> ChunkRenderBuffer {
-        let mut buf: ChunkRenderBuffer = [0; _];
+    fn cancel_outside(&mut self, origin: ChunkPos) {
+        let size = self.window_size;
+        let workers = &self.workers;
+        self.pending.retain(|&pos| {
+            let wanted = size.contains(origin, pos);
+            if !wanted {
+                workers.cancel(pos);
+            }
+            wanted
+        });
+    }
+
+    // Edited chunks stay loaded until they have been written, so nothing is
+    // lost when they are loaded again.
+    fn evict_outside(&mut self, origin: ChunkPos) {
+        let size = self.window_size;
+        let near =
+            |local: i32, len: u32| (-EVICT_MARGIN..len as i32 + EVICT_MARGIN).contains(&local);
+        let (unsaved, saving) = (&self.unsaved, &self.saving);
+        self.chunks.retain(|pos, _| {
+            let local = pos - origin;
+            (near(local.x, size.xz) && near(local.y, size.y) && near(local.z, size.xz))
+                || unsaved.contains(pos)
+                || saving.contains_key(pos)
+        });
+    }
+
+    pub fn is_pending(&self, x: i32, y: i32, z: i32) -> bool {
+        self.pending.contains(&ChunkPos::new(x, y, z))
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
 
-        for chunk_z in 0..CHUNK_XZ as usize {
-            for chunk_x in 0..CHUNK_XZ as usize {
-                for chunk_y in 0..CHUNK_Y as usize {
-                    let Some(chunk) = self.get(
-                        chunk_x as isize + x,
-                        chunk_y as isize + y,
-                        chunk_z as isize + z,
-                    ) else {
+    // Moves up to `max` generated chunks into the storage and returns how
+    // many were added. Chunks that were created in the meantime, for example
+    // by an edit, are kept and the generated copy is dropped, as are chunks
+    // whose request was cancelled.
+    pub fn integrate(&mut self, max: usize) -> usize {
+        let mut count = 0;
+        while count < max {
+            let Some((pos, chunk)) = self.workers.try_recv() else {
+                break;
+            };
+            if !self.pending.remove(&pos) || self.chunks.contains_key(&pos) {
+                continue;
+            }
+            self.chunks.insert(pos, chunk);
+            self.dirty.insert(pos);
+            count += 1;
+        }
+        count
+    }
+
+    // Unloaded chunks read as air.
+    pub fn get_block(&self, x: i32, y: i32, z: i32) -> block::BlockId {
+        let (pos, [lx, ly, lz]) = chunk_of(BlockPos::new(x, y, z));
+        self.get(pos.x, pos.y, pos.z)
+            .map_or(block::AIR, |chunk| chunk.get(lx, ly, lz))
+    }
+
+    pub fn set_block(&mut self, x: i32, y: i32, z: i32, block: block::BlockId) {
+        let (pos, [lx, ly, lz]) = chunk_of(BlockPos::new(x, y, z));
+        self.get_or_create(pos.x, pos.y, pos.z)
+            .set(lx, ly, lz, block);
+    }
+
+    // Fills the box between `min` and `max`, both inclusive. Chunks that are
+    // covered completely are replaced instead of being written voxel by voxel.
+    pub fn fill_region(&mut self, min: BlockPos, max: BlockPos, block: block::BlockId) {
+        if !block::is_valid(block) {
+            log::warn!("Ignoring invalid block id {block}");
+            return;
+        }
+        let (lo, hi) = (
+            BlockPos::new(min.x.min(max.x), min.y.min(max.y), min.z.min(max.z)),
+            BlockPos::new(min.x.max(max.x), min.y.max(max.y), min.z.max(max.z)),
+        );
+        let (chunk_lo, _) = chunk_of(lo);
+        let (chunk_hi, _) = chunk_of(hi);
+
+        for cx in chunk_lo.x..=chunk_hi.x {
+            for cz in chunk_lo.z..=chunk_hi.z {
+                for cy in chunk_lo.y..=chunk_hi.y {
+                    let base = chunk_min(ChunkPos::new(cx, cy, cz));
+                    let local_lo = (lo - base).map(|v| v.max(0) as usize);
+                    let local_hi = (hi - base).map(|v| v.min(CHUNK_SIZE as i32 - 1) as usize);
+
+                    let whole = local_lo == cgmath::Vector3::new(0, 0, 0)
+                        && local_hi == cgmath::Vector3::new(1, 1, 1) * (CHUNK_SIZE - 1);
+                    if whole {
+                        self.insert(cx, cy, cz, chunk::Chunk::filled(block));
                         continue;
-                    };
-                    let base_x = chunk_x * CHUNK_SIZE;
-                    let base_y = chunk_y * CHUNK_SIZE;
-                    let base_z = chunk_z * CHUNK_SIZE;
-
-                    for z in 0..CHUNK_SIZE {
-                        for x in 0..CHUNK_SIZE {
-                            for y in 0..CHUNK_SIZE {
-                                let gx = base_x + x;
-                                let gy = base_y + y;
-                                let gz = base_z + z;
-
-                                buf[gx
-                                    + gz * lattice::XZ as usize
-                                    + gy * (lattice::XZ * lattice::XZ) as usize] =
-                                    (chunk.blocks[x][z] >> y) & 1;
-
-                                // (gx as u32) << 16 | (gy as u32) << 8 | (gz as u32);
+                    }
+
+                    let chunk = self.get_or_create(cx, cy, cz);
+                    for x in local_lo.x..=local_hi.x {
+                        for z in local_lo.z..=local_hi.z {
+                            for y in local_lo.y..=local_hi.y {
+                                chunk.set(x, y, z, block);
                             }
                         }
                     }
                 }
             }
         }
+    }
+
+    pub fn clear_region(&mut self, min: BlockPos, max: BlockPos) {
+        self.fill_region(min, max, block::AIR);
+    }
+
+    // Block IDs of a single chunk laid out as x, then z, then y to match the
+    // chunk texture. Chunks that are not loaded upload as air.
+    pub fn copy_to_render_buffer(&self, x: i32, y: i32, z: i32) -> Box<ChunkRenderBuffer> {
+        let mut buf: Box<ChunkRenderBuffer> = Box::new([0; _]);
+
+        let Some(chunk) = self.get(x, y, z) else {
+            return buf;
+        };
+        if chunk.is_empty() {
+            return buf;
+        }
+
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                for y in 0..CHUNK_SIZE {
+                    buf[x + z * CHUNK_SIZE + y * CHUNK_SIZE * CHUNK_SIZE] =
+                        chunk.get(x, y, z) as u8;
+                }
+            }
+        }
+        buf
+    }
+
+    // Occupancy of every Y column of a chunk, laid out as x, then z.
+    pub fn copy_to_occupancy_buffer(&self, x: i32, y: i32, z: i32) -> Box<ChunkOccupancyBuffer> {
+        let mut buf: Box<ChunkOccupancyBuffer> = Box::new([0; _]);
+
+        let Some(chunk) = self.get(x, y, z) else {
+            return buf;
+        };
+        if chunk.is_empty() {
+            return buf;
+        }
+
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                buf[x + z * CHUNK_SIZE] = chunk.column(x, z);
+            }
+        }
+        buf
+    }
+
+    // Which bricks of a chunk hold anything but air, laid out like the
+    // brick texture.
+    pub fn copy_to_brick_buffer(&self, x: i32, y: i32, z: i32) -> ChunkBricks {
+        let mut buf = [0; _];
+        let Some(chunk) = self.get(x, y, z).filter(|chunk| !chunk.is_empty()) else {
+            return buf;
+        };
+
+        let bricks = brickmap::CHUNK_BRICKS;
+        let size = brickmap::BRICK_SIZE;
+        let mask = (1u32 << size) - 1;
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                let column = chunk.column(x, z);
+                for y in 0..bricks {
+                    if (column >> (y * size)) & mask != 0 {
+                        buf[x / size + z / size * bricks + y * bricks * bricks] = 1;
+                    }
+                }
+            }
+        }
         buf
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::FlatGenerator;
+    use crate::save::tests::TempDir;
+
+    #[test]
+    fn saved_and_removed_chunks_survive_reopening() {
+        let dir = TempDir::new("storage_save");
+        let generated = FlatGenerator::new(0).generate(ChunkPos::new(0, 0, 0));
+        {
+            let mut chunks = ChunkStorage::open(&dir.0, FlatGenerator::new(0)).unwrap();
+            chunks.set_block(-64, -32, -64, block::BRICK);
+            chunks.set_block(0, 100, 0, block::BRICK);
+            assert_eq!(chunks.save_in_background(), 2);
+            assert!(chunks.is_saving());
+            // waits for the background save, which wrote both chunks
+            assert_eq!(chunks.save().unwrap(), 2);
+            assert!(!chunks.is_saving());
+        }
+
+        let mut chunks = ChunkStorage::open(&dir.0, FlatGenerator::new(0)).unwrap();
+        chunks.load_window(0, 0, 0);
+        assert_eq!(chunks.get_block(-64, -32, -64), block::BRICK);
+        let (pos, _) = chunk_of(BlockPos::new(0, 100, 0));
+        chunks.load_window(pos.x, pos.y, pos.z);
+        assert_eq!(chunks.get_block(0, 100, 0), block::BRICK);
+
+        // removing clears the saved copy, the generator takes over again
+        chunks.remove(0, 0, 0);
+        assert_eq!(chunks.save().unwrap(), 1);
+        drop(chunks);
+        let mut chunks = ChunkStorage::open(&dir.0, FlatGenerator::new(0)).unwrap();
+        chunks.load_window(0, 0, 0);
+        assert_eq!(chunks.get(0, 0, 0), Some(&generated));
+    }
+
+    fn integrate_all(chunks: &mut ChunkStorage) {
+        while chunks.pending_count() > 0 {
+            if chunks.integrate(usize::MAX) == 0 {
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+        }
+    }
+
+    #[test]
+    fn moving_the_window_cancels_requests_and_evicts_far_chunks() {
+        let mut chunks = ChunkStorage::new(FlatGenerator::new(0));
+        chunks.set_window_size(WindowSize::new(2, 2));
+        chunks.request_window(0, 0, 0);
+        integrate_all(&mut chunks);
+        assert_eq!(chunks.len(), 8);
+        chunks.set_block(0, 0, 0, block::BRICK);
+        let (edited, _) = chunk_of(BlockPos::new(0, 0, 0));
+
+        // the old window is still pending when the window moves on
+        chunks.request_window(10, 0, 0);
+        chunks.request_window(20, 0, 0);
+        assert!(chunks.loaded().all(|pos| pos == edited || pos.x >= 20));
+        assert_eq!(chunks.pending_count(), 8);
+        assert!(!chunks.is_pending(10, 0, 0));
+        integrate_all(&mut chunks);
+        assert!(!chunks.is_loaded(10, 0, 0));
+        assert_eq!(chunks.len(), 9);
+        assert_eq!(chunks.get_block(0, 0, 0), block::BRICK);
+
+        // chunks just outside the window stay loaded
+        chunks.request_window(22, 0, 0);
+        assert!(chunks.is_loaded(20, 0, 0));
+        chunks.request_window(24, 0, 0);
+        assert!(!chunks.is_loaded(20, 0, 0));
+    }
+
+    #[test]
+    fn out_of_range_ids_never_reach_the_render_buffer() {
+        let mut chunks = ChunkStorage::new(FlatGenerator::new(-1000));
+        chunks.set_block(0, 0, 0, block::BRICK);
+        chunks.set_block(1, 0, 0, block::BRICK);
+        chunks.set_block(1, 0, 0, block::MAX_BLOCK_TYPES as block::BlockId + 44);
+        chunks.fill_region(
+            BlockPos::new(-64, -32, -64),
+            BlockPos::new(-33, -1, -33),
+            block::MAX_BLOCK_TYPES as block::BlockId,
+        );
+        assert_eq!(chunks.get_block(1, 0, 0), block::BRICK);
+        assert_eq!(chunks.get_block(-64, -32, -64), block::AIR);
+
+        let (pos, _) = chunk_of(BlockPos::new(0, 0, 0));
+        let buf = chunks.copy_to_render_buffer(pos.x, pos.y, pos.z);
+        assert!(buf
+            .iter()
+            .all(|&id| id == block::AIR as u8 || id == block::BRICK as u8));
+    }
+
+    #[test]
+    fn oversized_windows_are_rejected() {
+        assert_eq!("6x2".parse(), Ok(WindowSize::new(6, 2)));
+        assert!("65x1".parse::<WindowSize>().is_err());
+        assert!("1x65".parse::<WindowSize>().is_err());
+        // within the texture size but over the byte budget
+        assert!("64x3".parse::<WindowSize>().is_err());
+        assert!(WindowSize::new(8, 8).check(128).is_err());
+
+        assert_eq!(WindowSize::new(6, 2).limit(2048), WindowSize::new(6, 2));
+        assert_eq!(WindowSize::new(100, 100).limit(256), WindowSize::new(8, 8));
+        let limited = WindowSize::new(64, 64).limit(2048);
+        assert_eq!(limited.check(2048), Ok(()));
+        assert_eq!(limited.y, 64);
+        assert!(limited.texture_bytes() <= WindowSize::MAX_TEXTURE_BYTES);
+        assert!(WindowSize::new(limited.xz + 1, 64).check(2048).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::HashSet;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use crate::block::BlockRegistry;
+use crate::chunk::Chunk;
+use crate::chunk_storage::ChunkPos;
+use crate::generator::WorldGenerator;
+use crate::save::WorldSave;
+
+// Where chunks come from: the save if it has them, the generator otherwise.
+pub struct ChunkSource {
+    pub generator: Arc<dyn WorldGenerator>,
+    pub save: Option<WorldSave>,
+}
+
+impl ChunkSource {
+    pub fn load(&self, pos: ChunkPos) -> Chunk {
+        if let Some(save) = &self.save {
+            match save.load_chunk(pos) {
+                Ok(Some(chunk)) => return chunk,
+                Ok(None) => {}
+                Err(e) => log::error!("Failed to load chunk {pos:?}: {e}"),
+            }
+        }
+        self.generator.generate(pos)
+    }
+}
+
+// Pool of threads loading chunks in the order they were requested.
+// Finished chunks are collected on the owning thread with `try_recv`.
+// Requests that were cancelled before a worker got to them are skipped.
+pub struct ChunkWorkers {
+    jobs: Option<mpsc::Sender<ChunkPos>>,
+    results: mpsc::Receiver<(ChunkPos, Chunk)>,
+    threads: Vec<thread::JoinHandle<()>>,
+    stop: Arc<AtomicBool>,
+    wanted: Arc<Mutex<HashSet<ChunkPos>>>,
+}
+
+impl ChunkWorkers {
+    pub fn new(source: Arc<ChunkSource>, count: usize) -> Self {
+        let (jobs, job_receiver) = mpsc::channel::<ChunkPos>();
+        let (result_sender, results) = mpsc::channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let stop = Arc::new(AtomicBool::new(false));
+        let wanted = Arc::new(Mutex::new(HashSet::new()));
+
+        let threads = (0..count.max(1))
+            .map(|i| {
+                let source = source.clone();
+                let job_receiver = job_receiver.clone();
+                let result_sender = result_sender.clone();
+                let stop = stop.clone();
+                let wanted = wanted.clone();
+                thread::Builder::new()
+                    .name(format!("chunk worker {i}"))
+                    .spawn(move || loop {
+                        let job = job_receiver.lock().unwrap().recv();
+                        let Ok(pos) = job else {
+                            break;
+                        };
+                        if stop.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        if !wanted.lock().unwrap().remove(&pos) {
+                            continue;
+                        }
+                        let chunk = source.load(pos);
+                        if result_sender.send((pos, chunk)).is_err() {
+                            break;
+                        }
+                    })
+                    .expect("Failed to spawn chunk worker")
+            })
+            .collect();
+
+        Self {
+            jobs: Some(jobs),
+            results,
+            threads,
+            stop,
+            wanted,
+        }
+    }
+
+    pub fn with_default_threads(source: Arc<ChunkSource>) -> Self {
+        let count = thread::available_parallelism().map_or(1, |n| n.get().saturating_sub(1));
+        Self::new(source, count)
+    }
+
+    pub fn request(&self, pos: ChunkPos) {
+        if let Some(jobs) = &self.jobs {
+            self.wanted.lock().unwrap().insert(pos);
+            let _ = jobs.send(pos);
+        }
+    }
+
+    // A chunk that is already being loaded still arrives through `try_recv`.
+    pub fn cancel(&self, pos: ChunkPos) {
+        self.wanted.lock().unwrap().remove(&pos);
+    }
+
+    pub fn try_recv(&self) -> Option<(ChunkPos, Chunk)> {
+        self.results.try_recv().ok()
+    }
+}
+
+impl Drop for ChunkWorkers {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.jobs = None;
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Snapshot of everything one save writes. `None` clears a chunk from the
+// save.
+pub struct SaveJob {
+    pub block_types: BlockRegistry,
+    pub chunks: Vec<(ChunkPos, Option<Chunk>)>,
+}
+
+pub struct SaveDone {
+    pub chunks: Vec<ChunkPos>,
+    pub result: io::Result<()>,
+}
+
+// Thread writing saves in the order they were queued, so reading, merging
+// and compressing region files never holds up a frame. Dropping it waits
+// for the queued saves to finish.
+pub struct ChunkWriter {
+    jobs: Option<mpsc::Sender<SaveJob>>,
+    results: mpsc::Receiver<SaveDone>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl ChunkWriter {
+    pub fn new(save: WorldSave) -> Self {
+        let (jobs, job_receiver) = mpsc::channel::<SaveJob>();
+        let (result_sender, results) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("chunk writer".to_string())
+            .spawn(move || {
+                for job in job_receiver {
+                    let result = save.save_block_types(&job.block_types).and_then(|()| {
+                        save.save_chunks(
+                            job.chunks.iter().map(|(pos, chunk)| (*pos, chunk.as_ref())),
+                        )
+                    });
+                    let chunks = job.chunks.into_iter().map(|(pos, _)| pos).collect();
+                    // nobody is waiting for the result while shutting down
+                    let _ = result_sender.send(SaveDone { chunks, result });
+                }
+            })
+            .expect("Failed to spawn chunk writer");
+
+        Self {
+            jobs: Some(jobs),
+            results,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn write(&self, job: SaveJob) {
+        if let Some(jobs) = &self.jobs {
+            let _ = jobs.send(job);
+        }
+    }
+
+    pub fn try_recv(&self) -> Option<SaveDone> {
+        self.results.try_recv().ok()
+    }
+
+    // Blocks until the next save is done
+    pub fn recv(&self) -> Option<SaveDone> {
+        self.results.recv().ok()
+    }
+}
+
+impl Drop for ChunkWriter {
+    fn drop(&mut self) {
+        self.jobs = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
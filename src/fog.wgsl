@@ -0,0 +1,40 @@
+// Prepended to shader.wgsl and background.wgsl so blocks and sky fade into
+// the same fog.
+
+struct Fog {
+    color: vec3<f32>,
+    // extinction per block at `height`, zero disables fog
+    density: f32,
+    // light colour times the scattering strength
+    scatter_color: vec3<f32>,
+    height_falloff: f32,
+    // points at the light source
+    light_to: vec3<f32>,
+    height: f32,
+    cam_height: f32,
+    // distance at which everything is fogged
+    far: f32,
+}
+
+// Fraction of the colour at `dist` along the unit vector `dir` from the
+// camera replaced by fog. The density falls off exponentially with height,
+// which integrates along the ray in closed form.
+fn fog_amount(fog: Fog, dir: vec3<f32>, dist: f32) -> f32 {
+    if fog.density <= 0.0 {
+        return 0.0;
+    }
+    let k = max(fog.height_falloff, 1e-4);
+    let x = max(k * dir.y * dist, -80.0);
+    let spread = select((1.0 - exp(-x)) / x, 1.0, abs(x) < 1e-4);
+    let optical_depth = fog.density * exp(-k * (fog.cam_height - fog.height)) * dist * spread;
+    return 1.0 - exp(-optical_depth);
+}
+
+// Fog colour seen along `dir`, brightened towards the light by single
+// scattering with a Henyey-Greenstein phase function
+fn fog_color(fog: Fog, dir: vec3<f32>) -> vec3<f32> {
+    let g = 0.76;
+    let cos_theta = dot(dir, fog.light_to);
+    let phase = (1.0 - g * g) / (4.0 * 3.14159265 * pow(1.0 + g * g - 2.0 * g * cos_theta, 1.5));
+    return fog.color + fog.scatter_color * phase;
+}
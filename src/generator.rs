@@ -0,0 +1,304 @@
+use noise::{Fbm, MultiFractal, NoiseFn, OpenSimplex};
+
+use crate::block::{self, BlockId};
+use crate::chunk::{Chunk, CHUNK_SIZE};
+use crate::chunk_storage::{self, ChunkPos};
+
+// Generators are shared with the chunk workers and must produce the same
+// chunk for the same position every time they are asked.
+pub trait WorldGenerator: Send + Sync {
+    fn generate(&self, pos: ChunkPos) -> Chunk;
+}
+
+#[derive(Debug, Clone)]
+pub struct FlatGenerator {
+    pub height: i32,
+    pub top: BlockId,
+    pub filler: BlockId,
+    pub filler_depth: i32,
+    pub base: BlockId,
+}
+
+impl FlatGenerator {
+    pub fn new(height: i32) -> Self {
+        Self {
+            height,
+            top: block::GRASS,
+            filler: block::DIRT,
+            filler_depth: 3,
+            base: block::STONE,
+        }
+    }
+}
+
+impl WorldGenerator for FlatGenerator {
+    fn generate(&self, pos: ChunkPos) -> Chunk {
+        let min = chunk_storage::chunk_min(pos);
+        if min.y > self.height {
+            return Chunk::empty();
+        }
+        if min.y + (CHUNK_SIZE as i32) <= self.height - self.filler_depth {
+            return Chunk::filled(self.base);
+        }
+
+        let mut chunk = Chunk::empty();
+        for y in 0..CHUNK_SIZE {
+            let depth = self.height - (min.y + y as i32);
+            let block = match depth {
+                ..0 => continue,
+                0 => self.top,
+                d if d <= self.filler_depth => self.filler,
+                _ => self.base,
+            };
+            for x in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    chunk.set(x, y, z, block);
+                }
+            }
+        }
+        chunk
+    }
+}
+
+// Solid wherever 3D noise exceeds `threshold`, which produces floating
+// islands and overhangs rather than a terrain surface.
+pub struct NoiseGenerator {
+    pub threshold: f64,
+    pub block: BlockId,
+    seed: u32,
+    noise: Fbm<OpenSimplex>,
+}
+
+impl NoiseGenerator {
+    pub fn new(seed: u32) -> Self {
+        Self::with_params(seed, 0.04, 3, 0.04, block::STONE)
+    }
+
+    pub fn with_params(
+        seed: u32,
+        frequency: f64,
+        octaves: usize,
+        threshold: f64,
+        block: BlockId,
+    ) -> Self {
+        Self {
+            threshold,
+            block,
+            seed,
+            noise: Fbm::<OpenSimplex>::new(seed)
+                .set_frequency(frequency)
+                .set_octaves(octaves),
+        }
+    }
+
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+}
+
+impl WorldGenerator for NoiseGenerator {
+    fn generate(&self, pos: ChunkPos) -> Chunk {
+        let min = chunk_storage::chunk_min(pos);
+        let mut chunk = Chunk::empty();
+
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                for y in 0..CHUNK_SIZE {
+                    let value = self.noise.get([
+                        (min.x + x as i32) as f64,
+                        (min.y + y as i32) as f64,
+                        (min.z + z as i32) as f64,
+                    ]);
+                    if value >= self.threshold {
+                        chunk.set(x, y, z, self.block);
+                    }
+                }
+            }
+        }
+        chunk
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TerrainParams {
+    pub base_height: i32,
+    pub amplitude: f64,
+    pub frequency: f64,
+    pub octaves: usize,
+    pub sea_level: i32,
+    pub snow_line: i32,
+    pub dirt_depth: i32,
+    pub caves: bool,
+    pub cave_frequency: f64,
+    pub cave_threshold: f64,
+}
+
+impl Default for TerrainParams {
+    fn default() -> Self {
+        Self {
+            base_height: -4,
+            amplitude: 20.0,
+            frequency: 0.008,
+            octaves: 5,
+            sea_level: -8,
+            snow_line: 14,
+            dirt_depth: 3,
+            caves: true,
+            cave_frequency: 0.03,
+            cave_threshold: 0.08,
+        }
+    }
+}
+
+// Heightmap terrain with grass, sand near sea level and snow on peaks.
+// Caves are carved where two independent noise fields are both close to
+// zero, which gives long connected tunnels.
+pub struct TerrainGenerator {
+    seed: u32,
+    params: TerrainParams,
+    height: Fbm<OpenSimplex>,
+    cave_a: OpenSimplex,
+    cave_b: OpenSimplex,
+}
+
+impl TerrainGenerator {
+    pub fn new(seed: u32) -> Self {
+        Self::with_params(seed, TerrainParams::default())
+    }
+
+    pub fn with_params(seed: u32, params: TerrainParams) -> Self {
+        Self {
+            seed,
+            height: Fbm::<OpenSimplex>::new(seed)
+                .set_frequency(params.frequency)
+                .set_octaves(params.octaves),
+            cave_a: OpenSimplex::new(seed.wrapping_add(1)),
+            cave_b: OpenSimplex::new(seed.wrapping_add(2)),
+            params,
+        }
+    }
+
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    pub fn params(&self) -> &TerrainParams {
+        &self.params
+    }
+
+    pub fn surface_height(&self, x: i32, z: i32) -> i32 {
+        let h = self.height.get([x as f64, z as f64]);
+        self.params.base_height + (h * self.params.amplitude).round() as i32
+    }
+
+    fn is_cave(&self, x: i32, y: i32, z: i32) -> bool {
+        let f = self.params.cave_frequency;
+        let p = [x as f64 * f, y as f64 * f * 1.5, z as f64 * f];
+        let a = self.cave_a.get(p);
+        let b = self.cave_b.get(p);
+        a * a + b * b < self.params.cave_threshold * self.params.cave_threshold
+    }
+
+    fn surface_block(&self, height: i32) -> BlockId {
+        if height >= self.params.snow_line {
+            block::SNOW
+        } else if height <= self.params.sea_level + 1 {
+            block::SAND
+        } else {
+            block::GRASS
+        }
+    }
+}
+
+impl WorldGenerator for TerrainGenerator {
+    fn generate(&self, pos: ChunkPos) -> Chunk {
+        let min = chunk_storage::chunk_min(pos);
+        let mut chunk = Chunk::empty();
+
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let (wx, wz) = (min.x + x as i32, min.z + z as i32);
+                let height = self.surface_height(wx, wz);
+                let top = (height - min.y).min(CHUNK_SIZE as i32 - 1);
+
+                for y in 0..=top {
+                    let wy = min.y + y;
+                    if self.params.caves && self.is_cave(wx, wy, wz) {
+                        continue;
+                    }
+                    let block = match height - wy {
+                        0 => self.surface_block(height),
+                        d if d <= self.params.dirt_depth => {
+                            if height <= self.params.sea_level + 1 {
+                                block::SAND
+                            } else {
+                                block::DIRT
+                            }
+                        }
+                        _ => block::STONE,
+                    };
+                    chunk.set(x, y as usize, z, block);
+                }
+            }
+        }
+        chunk
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_storage::BlockPos;
+
+    // chunk holding world x and z 0 to 31 around the default terrain height
+    const SURFACE: ChunkPos = ChunkPos::new(2, 0, 2);
+
+    fn block_at(generator: &impl WorldGenerator, x: i32, y: i32, z: i32) -> BlockId {
+        let (pos, [lx, ly, lz]) = chunk_storage::chunk_of(BlockPos::new(x, y, z));
+        generator.generate(pos).get(lx, ly, lz)
+    }
+
+    #[test]
+    fn flat_layers() {
+        let flat = FlatGenerator::new(10);
+        assert_eq!(block_at(&flat, 5, 11, -7), block::AIR);
+        assert_eq!(block_at(&flat, 5, 10, -7), block::GRASS);
+        for y in 7..10 {
+            assert_eq!(block_at(&flat, 5, y, -7), block::DIRT);
+        }
+        assert_eq!(block_at(&flat, 5, 6, -7), block::STONE);
+        assert_eq!(
+            flat.generate(ChunkPos::new(0, -3, 0)),
+            Chunk::filled(block::STONE)
+        );
+        assert_eq!(flat.generate(ChunkPos::new(0, 3, 0)), Chunk::empty());
+    }
+
+    #[test]
+    fn noise_depends_only_on_the_seed() {
+        let chunk = NoiseGenerator::new(7).generate(SURFACE);
+        assert!(!chunk.is_empty());
+        assert_eq!(NoiseGenerator::new(7).generate(SURFACE), chunk);
+        assert_ne!(NoiseGenerator::new(8).generate(SURFACE), chunk);
+    }
+
+    #[test]
+    fn terrain_depends_only_on_the_seed() {
+        let chunk = TerrainGenerator::new(7).generate(SURFACE);
+        assert!(!chunk.is_empty());
+        assert_eq!(TerrainGenerator::new(7).generate(SURFACE), chunk);
+        assert_ne!(TerrainGenerator::new(8).generate(SURFACE), chunk);
+
+        // the surface block sits at the reported height
+        let terrain = TerrainGenerator::with_params(
+            7,
+            TerrainParams {
+                caves: false,
+                ..Default::default()
+            },
+        );
+        let height = terrain.surface_height(3, 4);
+        assert_ne!(block_at(&terrain, 3, height, 4), block::AIR);
+        assert_eq!(block_at(&terrain, 3, height + 1, 4), block::AIR);
+    }
+}
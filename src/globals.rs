@@ -1,12 +1,52 @@
+use crate::shadow;
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct GlobalsUniform {
     pub proj_view_mat: [[f32; 4]; 4],
-    pub light_mat: [[f32; 4]; 4],
+    pub inv_proj_view_mat: [[f32; 4]; 4],
+    pub light_mats: [[[f32; 4]; 4]; shadow::CASCADE_COUNT],
     pub cam_pos: [f32; 3],
-    pub cam_dir: [f32; 3],
-    pub light_dir: [f32; 3],
     pub _pad0: u32,
+    pub cam_dir: [f32; 3],
     pub _pad1: u32,
+    pub light_dir: [f32; 3],
     pub grid_lines: u32,
+    pub window_offset: [i32; 3],
+    pub has_target: u32,
+    pub target_voxel: [i32; 3],
+    pub _pad2: u32,
+    pub target_normal: [i32; 3],
+    pub traced_shadows: u32,
+    pub resolution: [u32; 2],
+    pub ao_enabled: u32,
+    pub ao_strength: f32,
+    pub shadow_bias: f32,
+    pub shadow_normal_bias: f32,
+    pub shadow_pcf_radius: i32,
+    pub _pad3: u32,
+    pub cascade_splits: [f32; shadow::CASCADE_COUNT],
+    pub light_color: [f32; 3],
+    pub _pad4: u32,
+    pub ambient_color: [f32; 3],
+    pub _pad5: u32,
+    pub fog: FogUniform,
+    // texel of the chunk texture holding the window's first voxel
+    pub window_wrap: [u32; 3],
+    pub _pad6: u32,
+}
+
+// Mirrors `Fog` in fog.wgsl, shared by the main and background uniforms
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct FogUniform {
+    pub color: [f32; 3],
+    pub density: f32,
+    pub scatter_color: [f32; 3],
+    pub height_falloff: f32,
+    pub light_to: [f32; 3],
+    pub height: f32,
+    pub cam_height: f32,
+    pub far: f32,
+    pub _pad: [u32; 2],
 }
@@ -0,0 +1,213 @@
+use std::fmt;
+use std::sync::mpsc;
+
+use crate::{
+    camera::Camera,
+    chunk_storage::{self, ChunkStorage},
+    renderer::Renderer,
+};
+
+#[derive(Debug)]
+pub enum HeadlessError {
+    Adapter(wgpu::RequestAdapterError),
+    Device(wgpu::RequestDeviceError),
+    Poll(wgpu::PollError),
+    Readback(wgpu::BufferAsyncError),
+}
+
+impl fmt::Display for HeadlessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeadlessError::Adapter(e) => write!(f, "no suitable adapter: {e}"),
+            HeadlessError::Device(e) => write!(f, "failed to create device: {e}"),
+            HeadlessError::Poll(e) => write!(f, "failed to wait for the device: {e}"),
+            HeadlessError::Readback(e) => write!(f, "failed to read back the frame: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for HeadlessError {}
+
+// Renders into an offscreen texture instead of a window surface and reads
+// every frame back to the CPU.
+pub struct HeadlessRenderer {
+    renderer: Renderer,
+    target: wgpu::Texture,
+    readback: wgpu::Buffer,
+}
+
+impl HeadlessRenderer {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+    // `force_fallback_adapter` picks a software adapter where the platform
+    // has one, for machines without a GPU.
+    pub async fn new(
+        width: u32,
+        height: u32,
+        chunks: &ChunkStorage,
+        force_fallback_adapter: bool,
+    ) -> Result<Self, HeadlessError> {
+        let instance = wgpu::Instance::default();
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                force_fallback_adapter,
+                compatible_surface: None,
+                ..Default::default()
+            })
+            .await
+            .map_err(HeadlessError::Adapter)?;
+        log::info!("Headless adapter: {:?}", adapter.get_info());
+
+        let (device, queue) = adapter
+            .request_device(&Default::default())
+            .await
+            .map_err(HeadlessError::Device)?;
+
+        let (width, height) = (width.max(1), height.max(1));
+        let mut renderer = Renderer::new(
+            device,
+            queue,
+            Self::FORMAT,
+            width,
+            height,
+            chunks.block_types(),
+        );
+        // every frame has to be complete
+        renderer.mesh_builds_per_frame = usize::MAX;
+        let (target, readback) = Self::create_target(renderer.device(), width, height);
+
+        Ok(Self {
+            renderer,
+            target,
+            readback,
+        })
+    }
+
+    pub fn renderer(&self) -> &Renderer {
+        &self.renderer
+    }
+
+    pub fn renderer_mut(&mut self) -> &mut Renderer {
+        &mut self.renderer
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.renderer.resize(width, height);
+        (self.target, self.readback) = Self::create_target(self.renderer.device(), width, height);
+    }
+
+    // Renders one frame from the camera pose. The window around the camera
+    // is loaded synchronously first so the image never shows missing chunks.
+    pub fn render(
+        &mut self,
+        camera: &Camera,
+        chunks: &mut ChunkStorage,
+    ) -> Result<image::RgbaImage, HeadlessError> {
+        let (width, height) = (self.renderer.width(), self.renderer.height());
+        let mut camera = camera.clone();
+        camera.resize(width, height);
+
+        let origin = chunks.window_size().around(camera.position());
+        chunks.load_window(origin.x, origin.y, origin.z);
+        self.renderer.upload_chunks(chunks, &camera);
+
+        let view = self.target.create_view(&Default::default());
+        self.renderer.render(&view, &camera, 0);
+
+        let device = self.renderer.device();
+        let padded_row = Self::padded_row(width);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("readback encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            self.target.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &self.readback,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            self.target.size(),
+        );
+        self.renderer.queue().submit([encoder.finish()]);
+
+        let (sender, receiver) = mpsc::channel();
+        let slice = self.readback.slice(..);
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device
+            .poll(wgpu::PollType::wait_indefinitely())
+            .map_err(HeadlessError::Poll)?;
+        receiver
+            .recv()
+            .expect("readback callback dropped")
+            .map_err(HeadlessError::Readback)?;
+
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks_exact(padded_row as usize) {
+                pixels.extend_from_slice(&row[..(width * 4) as usize]);
+            }
+        }
+        self.readback.unmap();
+
+        Ok(image::RgbaImage::from_raw(width, height, pixels).expect("readback size mismatch"))
+    }
+
+    // Rows copied out of a texture must be aligned to 256 bytes
+    fn padded_row(width: u32) -> u32 {
+        (width * 4).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+    }
+
+    fn create_target(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+    ) -> (wgpu::Texture, wgpu::Buffer) {
+        let target = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("headless target"),
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT,
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("headless readback buffer"),
+            size: (Self::padded_row(width) * height) as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        (target, readback)
+    }
+}
+
+// One-off render of a camera pose at the given resolution.
+pub fn render_to_image(
+    camera: &Camera,
+    width: u32,
+    height: u32,
+    chunks: &mut ChunkStorage,
+    force_fallback_adapter: bool,
+) -> Result<image::RgbaImage, HeadlessError> {
+    let mut renderer = pollster::block_on(HeadlessRenderer::new(
+        width,
+        height,
+        chunks,
+        force_fallback_adapter,
+    ))?;
+    renderer.render(camera, chunks)
+}
@@ -1,213 +1,79 @@
 use crate::vertex::{self, Vertex};
-use cgmath;
 
-pub const XZ: u32 = 128;
-pub const Y: u32 = 64;
-pub const NUM_VERTICES: usize = (8 * (XZ + 1) + 4 * (Y + 1)) as usize;
-pub const NUM_INDICES: usize = (12 * (XZ + 1) + 6 * (Y + 1)) as usize;
-
-pub const MAX_X: i32 = (XZ as i32) / 2 - 1;
-pub const MAX_Z: i32 = (XZ as i32) / 2 - 1;
-pub const MAX_Y: i32 = (Y as i32) / 2 - 1;
-pub const MIN_X: i32 = -(XZ as i32) / 2;
-pub const MIN_Z: i32 = -(XZ as i32) / 2;
-pub const MIN_Y: i32 = -(Y as i32) / 2;
-
-pub const CORNERS: &[cgmath::Vector3<f32>] = &[
-    cgmath::Vector3 {
-        x: MIN_X as f32,
-        y: MIN_Y as f32,
-        z: MIN_Z as f32,
-    },
-    cgmath::Vector3 {
-        x: MAX_X as f32,
-        y: MIN_Y as f32,
-        z: MIN_Z as f32,
-    },
-    cgmath::Vector3 {
-        x: MIN_X as f32,
-        y: MAX_Y as f32,
-        z: MIN_Z as f32,
-    },
-    cgmath::Vector3 {
-        x: MAX_X as f32,
-        y: MAX_Y as f32,
-        z: MIN_Z as f32,
-    },
-    cgmath::Vector3 {
-        x: MIN_X as f32,
-        y: MIN_Y as f32,
-        z: MAX_Z as f32,
-    },
-    cgmath::Vector3 {
-        x: MAX_X as f32,
-        y: MIN_Y as f32,
-        z: MAX_Z as f32,
-    },
-    cgmath::Vector3 {
-        x: MIN_X as f32,
-        y: MAX_Y as f32,
-        z: MAX_Z as f32,
-    },
-    cgmath::Vector3 {
-        x: MAX_X as f32,
-        y: MAX_Y as f32,
-        z: MAX_Z as f32,
-    },
-];
-
-#[repr(C)]
+// One quad on every boundary between voxel layers of a window `xz` blocks
+// wide and `y` blocks high, spanning 0 to the window size on each axis.
 #[derive(Debug, Clone)]
 pub struct Lattice {
-    pub vertices: [Vertex; NUM_VERTICES],
-    pub indices: [u16; NUM_INDICES],
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
 }
 
 impl Lattice {
-    pub const fn new() -> Self {
+    pub fn new(xz: u32, y: u32) -> Self {
+        let quads = 2 * (xz as usize + 1) + y as usize + 1;
         let mut s = Self {
-            vertices: [Vertex {
-                pos: [0.0, 0.0, 0.0],
-                axis: 0,
-            }; NUM_VERTICES],
-            indices: [0; NUM_INDICES],
+            vertices: Vec::with_capacity(quads * 4),
+            indices: Vec::with_capacity(quads * 6),
         };
 
-        let mut v = 0;
-        let mut i = 0;
-        let mut xz = 0;
-        while xz <= XZ {
-            s.vertices[v + 0] = Vertex {
-                pos: [
-                    xz as f32 - (XZ / 2) as f32,
-                    -(Y as f32 / 2.0),
-                    -(XZ as f32 / 2.0),
-                ],
-                axis: vertex::AXIS_X,
-            };
-            s.vertices[v + 1] = Vertex {
-                pos: [
-                    xz as f32 - (XZ / 2) as f32,
-                    -(Y as f32 / 2.0),
-                    (XZ as f32 / 2.0),
-                ],
-                axis: vertex::AXIS_X,
-            };
-            s.vertices[v + 2] = Vertex {
-                pos: [
-                    xz as f32 - (XZ / 2) as f32,
-                    (Y as f32 / 2.0),
-                    -(XZ as f32 / 2.0),
-                ],
-                axis: vertex::AXIS_X,
-            };
-            s.vertices[v + 3] = Vertex {
-                pos: [
-                    xz as f32 - (XZ / 2) as f32,
-                    (Y as f32 / 2.0),
-                    (XZ as f32 / 2.0),
-                ],
-                axis: vertex::AXIS_X,
-            };
-            s.indices[i + 0] = v as u16 + 0;
-            s.indices[i + 1] = v as u16 + 1;
-            s.indices[i + 2] = v as u16 + 2;
-            s.indices[i + 3] = v as u16 + 1;
-            s.indices[i + 4] = v as u16 + 3;
-            s.indices[i + 5] = v as u16 + 2;
-
-            v += 4;
-            i += 6;
-
-            s.vertices[v + 0] = Vertex {
-                pos: [
-                    -(XZ as f32 / 2.0),
-                    -(Y as f32 / 2.0),
-                    xz as f32 - (XZ / 2) as f32,
-                ],
-                axis: vertex::AXIS_Z,
-            };
-            s.vertices[v + 1] = Vertex {
-                pos: [
-                    -(XZ as f32 / 2.0),
-                    (Y as f32 / 2.0),
-                    xz as f32 - (XZ / 2) as f32,
-                ],
-                axis: vertex::AXIS_Z,
-            };
-            s.vertices[v + 2] = Vertex {
-                pos: [
-                    (XZ as f32 / 2.0),
-                    -(Y as f32 / 2.0),
-                    xz as f32 - (XZ / 2) as f32,
-                ],
-                axis: vertex::AXIS_Z,
-            };
-            s.vertices[v + 3] = Vertex {
-                pos: [
-                    (XZ as f32 / 2.0),
-                    (Y as f32 / 2.0),
-                    xz as f32 - (XZ / 2) as f32,
-                ],
-                axis: vertex::AXIS_Z,
-            };
-            s.indices[i + 0] = v as u16 + 0;
-            s.indices[i + 1] = v as u16 + 1;
-            s.indices[i + 2] = v as u16 + 2;
-            s.indices[i + 3] = v as u16 + 1;
-            s.indices[i + 4] = v as u16 + 3;
-            s.indices[i + 5] = v as u16 + 2;
-
-            xz += 1;
-            v += 4;
-            i += 6;
+        // grouped by axis so `plane_indices` can find every plane
+        let (w, h) = (xz as f32, y as f32);
+        for i in 0..=xz {
+            let i = i as f32;
+            s.quad(
+                vertex::AXIS_X,
+                [[i, 0.0, 0.0], [i, 0.0, w], [i, h, 0.0], [i, h, w]],
+            );
         }
-
-        let mut y = 0;
-        while y <= Y {
-            s.vertices[v + 0] = Vertex {
-                pos: [
-                    -(XZ as f32 / 2.0),
-                    y as f32 - (Y as f32 / 2.0),
-                    -(XZ as f32 / 2.0),
-                ],
-                axis: vertex::AXIS_Y,
-            };
-            s.vertices[v + 1] = Vertex {
-                pos: [
-                    -(XZ as f32 / 2.0),
-                    y as f32 - (Y as f32 / 2.0),
-                    (XZ as f32 / 2.0),
-                ],
-                axis: vertex::AXIS_Y,
-            };
-            s.vertices[v + 2] = Vertex {
-                pos: [
-                    (XZ as f32 / 2.0),
-                    y as f32 - (Y as f32 / 2.0),
-                    -(XZ as f32 / 2.0),
-                ],
-                axis: vertex::AXIS_Y,
-            };
-            s.vertices[v + 3] = Vertex {
-                pos: [
-                    (XZ as f32 / 2.0),
-                    y as f32 - (Y as f32 / 2.0),
-                    (XZ as f32 / 2.0),
-                ],
-                axis: vertex::AXIS_Y,
-            };
-            s.indices[i + 0] = v as u16 + 0;
-            s.indices[i + 1] = v as u16 + 1;
-            s.indices[i + 2] = v as u16 + 2;
-            s.indices[i + 3] = v as u16 + 1;
-            s.indices[i + 4] = v as u16 + 3;
-            s.indices[i + 5] = v as u16 + 2;
-
-            y += 1;
-            v += 4;
-            i += 6;
+        for i in 0..=xz {
+            let i = i as f32;
+            s.quad(
+                vertex::AXIS_Z,
+                [[0.0, 0.0, i], [0.0, h, i], [w, 0.0, i], [w, h, i]],
+            );
+        }
+        for j in 0..=y {
+            let j = j as f32;
+            s.quad(
+                vertex::AXIS_Y,
+                [[0.0, j, 0.0], [0.0, j, w], [w, j, 0.0], [w, j, w]],
+            );
         }
         s
     }
+
+    fn quad(&mut self, axis: u32, corners: [[f32; 3]; 4]) {
+        let v = self.vertices.len() as u32;
+        self.vertices
+            .extend(corners.map(|pos| Vertex { pos, axis }));
+        self.indices.extend([v, v + 1, v + 2, v + 1, v + 3, v + 2]);
+    }
+
+    // Index range of plane `i` along `axis` in a lattice `xz` blocks wide
+    pub fn plane_indices(xz: u32, axis: u32, i: u32) -> std::ops::Range<u32> {
+        let quad = match axis {
+            vertex::AXIS_X => i,
+            vertex::AXIS_Z => xz + 1 + i,
+            _ => 2 * (xz + 1) + i,
+        };
+        quad * 6..quad * 6 + 6
+    }
+
+    pub fn index_data(&self) -> (Vec<u8>, wgpu::IndexFormat) {
+        vertex::index_data(self.vertices.len(), &self.indices)
+    }
+}
+
+pub fn corners(xz: u32, y: u32) -> [cgmath::Vector3<f32>; 8] {
+    let (w, h) = (xz as f32, y as f32);
+    [
+        cgmath::Vector3::new(0.0, 0.0, 0.0),
+        cgmath::Vector3::new(w, 0.0, 0.0),
+        cgmath::Vector3::new(0.0, h, 0.0),
+        cgmath::Vector3::new(w, h, 0.0),
+        cgmath::Vector3::new(0.0, 0.0, w),
+        cgmath::Vector3::new(w, 0.0, w),
+        cgmath::Vector3::new(0.0, h, w),
+        cgmath::Vector3::new(w, h, w),
+    ]
 }
@@ -1,14 +1,29 @@
 #![allow(dead_code, unused)]
 
 mod background;
-mod camera;
-mod chunk;
-mod chunk_storage;
+pub mod block;
+pub mod block_textures;
+mod brickmap;
+pub mod camera;
+pub mod chunk;
+pub mod chunk_storage;
+mod chunk_worker;
+pub mod generator;
 mod globals;
+pub mod headless;
 mod lattice;
+mod mesh;
+pub mod raycast;
+pub mod renderer;
+pub mod save;
+mod shaders;
+mod shadow;
 mod state;
+pub mod time_of_day;
 mod vertex;
-mod shadow;
+pub mod vox;
+
+use std::path::{Path, PathBuf};
 
 use winit::{
     application::ApplicationHandler,
@@ -17,13 +32,50 @@ use winit::{
     window::WindowAttributes,
 };
 
+// Where the world is saved and block textures are loaded from
+#[derive(Debug, Clone)]
+pub struct DataPaths {
+    pub world: PathBuf,
+    pub textures: PathBuf,
+}
+
+impl DataPaths {
+    // Next to the executable, so they are found whatever the working
+    // directory is
+    pub fn next_to_executable() -> Self {
+        let dir = std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(Path::to_path_buf))
+            .unwrap_or_else(|| PathBuf::from("."));
+        Self {
+            world: dir.join("world"),
+            textures: dir.join("textures"),
+        }
+    }
+}
+
 pub struct AppHandler<'a> {
     state: Option<state::State<'a>>,
+    window_size: chunk_storage::WindowSize,
+    paths: DataPaths,
 }
 
 impl AppHandler<'_> {
     pub fn new() -> Self {
-        Self { state: None }
+        Self::with_window_size(chunk_storage::WindowSize::DEFAULT)
+    }
+
+    pub fn with_window_size(window_size: chunk_storage::WindowSize) -> Self {
+        Self {
+            state: None,
+            window_size,
+            paths: DataPaths::next_to_executable(),
+        }
+    }
+
+    pub fn with_data_paths(mut self, paths: DataPaths) -> Self {
+        self.paths = paths;
+        self
     }
 }
 
@@ -35,7 +87,11 @@ impl ApplicationHandler for AppHandler<'_> {
             .expect("Failed to create window");
         window.set_cursor_visible(false);
         window.set_cursor_grab(winit::window::CursorGrabMode::Confined);
-        self.state = Some(pollster::block_on(state::State::new(window)));
+        self.state = Some(pollster::block_on(state::State::new(
+            window,
+            self.window_size,
+            self.paths.clone(),
+        )));
     }
 
     fn device_event(
@@ -103,6 +159,12 @@ impl ApplicationHandler for AppHandler<'_> {
         };
     }
 
+    fn exiting(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop) {
+        if let Some(state) = self.state.as_mut() {
+            state.save_world();
+        }
+    }
+
     fn about_to_wait(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop) {
         let window = self.state.as_ref().unwrap().window();
         window.request_redraw();
@@ -1,13 +1,45 @@
 use env_logger;
 use winit::event_loop::EventLoop;
 
-use voxel_render::AppHandler;
+use voxel_render::{chunk_storage::WindowSize, AppHandler, DataPaths};
 
 fn main() {
     env_logger::init();
     let event_loop = EventLoop::new().unwrap();
 
-    let mut handler = AppHandler::new();
+    // `--window 6x2` sets the render window size in chunks
+    let args: Vec<String> = std::env::args().collect();
+    let window_size = match option(&args, "--window", "a size like 6x2") {
+        Some(size) => size.parse::<WindowSize>().unwrap_or_else(|e| {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }),
+        None => WindowSize::DEFAULT,
+    };
+
+    // `--world <dir>` and `--textures <dir>` override the directories next
+    // to the executable
+    let mut paths = DataPaths::next_to_executable();
+    if let Some(dir) = option(&args, "--world", "a directory") {
+        paths.world = dir.into();
+    }
+    if let Some(dir) = option(&args, "--textures", "a directory") {
+        paths.textures = dir.into();
+    }
+
+    let mut handler = AppHandler::with_window_size(window_size).with_data_paths(paths);
 
     let _ = event_loop.run_app(&mut handler);
 }
+
+// Value following `name` on the command line, exits if it is missing
+fn option<'a>(args: &'a [String], name: &str, expected: &str) -> Option<&'a str> {
+    let i = args.iter().position(|arg| arg == name)?;
+    match args.get(i + 1) {
+        Some(value) => Some(value),
+        None => {
+            eprintln!("{name} needs {expected}");
+            std::process::exit(2);
+        }
+    }
+}
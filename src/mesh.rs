@@ -0,0 +1,323 @@
+use crate::{
+    block,
+    chunk::CHUNK_SIZE,
+    chunk_storage::{self, BlockPos, ChunkPos, ChunkStorage, WindowSize},
+    vertex::{self, Vertex},
+};
+
+const S: usize = CHUNK_SIZE;
+// the chunk plus one layer of each neighbour
+const PADDED: usize = CHUNK_SIZE + 2;
+
+// Which neighbours of a chunk are inside the render window, per axis.
+// Voxels outside the window read as air, like they do in the shader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Neighbours {
+    pub low: [bool; 3],
+    pub high: [bool; 3],
+}
+
+impl Neighbours {
+    pub fn in_window(size: WindowSize, origin: ChunkPos, pos: ChunkPos) -> Self {
+        let mut neighbours = Self {
+            low: [false; 3],
+            high: [false; 3],
+        };
+        for axis in 0..3 {
+            let mut step = ChunkPos::new(0, 0, 0);
+            step[axis] = 1;
+            neighbours.low[axis] = size.contains(origin, pos - step);
+            neighbours.high[axis] = size.contains(origin, pos + step);
+        }
+        neighbours
+    }
+}
+
+// Greedy-merged quads on every boundary between a solid voxel and air in a
+// chunk, in world coordinates. A chunk owns the boundaries on its low side
+// of each axis, and those on its high side only if the neighbour there is
+// outside the window.
+#[derive(Debug, Clone, Default)]
+pub struct ChunkMesh {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+}
+
+impl ChunkMesh {
+    pub fn build(chunks: &ChunkStorage, pos: ChunkPos, neighbours: Neighbours) -> Self {
+        let solid = Self::solid(chunks, pos, neighbours);
+        let origin = chunk_storage::chunk_min(pos);
+        let mut mesh = Self::default();
+
+        // 1 where the solid voxel is below the boundary, 2 where it is above
+        let mut mask = [0u8; S * S];
+        for axis in 0..3 {
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+            let planes = if neighbours.high[axis] { S } else { S + 1 };
+            for k in 0..planes {
+                for a in 0..S {
+                    for b in 0..S {
+                        let mut p = [0; 3];
+                        (p[axis], p[u], p[v]) = (k, a + 1, b + 1);
+                        let below = solid[padded_index(p)];
+                        p[axis] = k + 1;
+                        let above = solid[padded_index(p)];
+                        mask[a * S + b] = match (below, above) {
+                            (true, false) => 1,
+                            (false, true) => 2,
+                            _ => 0,
+                        };
+                    }
+                }
+                mesh.merge(&mut mask, axis, k, origin);
+            }
+        }
+        mesh
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    pub fn index_data(&self) -> (Vec<u8>, wgpu::IndexFormat) {
+        vertex::index_data(self.vertices.len(), &self.indices)
+    }
+
+    // Solid voxels of the chunk and the layers of its low neighbours in the
+    // window that touch it, indexed with `padded_index`. The high layers stay
+    // air: boundaries with a high neighbour in the window belong to that
+    // neighbour, and those outside it face air.
+    fn solid(chunks: &ChunkStorage, pos: ChunkPos, neighbours: Neighbours) -> Vec<bool> {
+        let mut solid = vec![false; PADDED * PADDED * PADDED];
+        if let Some(chunk) = chunks.get(pos.x, pos.y, pos.z).filter(|c| !c.is_empty()) {
+            for x in 0..S {
+                for y in 0..S {
+                    for z in 0..S {
+                        solid[padded_index([x + 1, y + 1, z + 1])] =
+                            chunk.get(x, y, z) != block::AIR;
+                    }
+                }
+            }
+        }
+
+        for axis in 0..3 {
+            if !neighbours.low[axis] {
+                continue;
+            }
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+            let mut neighbour = pos;
+            neighbour[axis] -= 1;
+            let Some(chunk) = chunks.get(neighbour.x, neighbour.y, neighbour.z) else {
+                continue;
+            };
+            for a in 0..S {
+                for b in 0..S {
+                    let (mut c, mut p) = ([0; 3], [0; 3]);
+                    (c[axis], c[u], c[v]) = (S - 1, a, b);
+                    (p[axis], p[u], p[v]) = (0, a + 1, b + 1);
+                    solid[padded_index(p)] = chunk.get(c[0], c[1], c[2]) != block::AIR;
+                }
+            }
+        }
+        solid
+    }
+
+    // Turns the faces in `mask` into as few rectangles as possible, growing
+    // each one along v first and then along u, and clears the mask.
+    fn merge(&mut self, mask: &mut [u8; S * S], axis: usize, k: usize, origin: BlockPos) {
+        for a in 0..S {
+            let mut b = 0;
+            while b < S {
+                let face = mask[a * S + b];
+                if face == 0 {
+                    b += 1;
+                    continue;
+                }
+
+                let mut height = 1;
+                while b + height < S && mask[a * S + b + height] == face {
+                    height += 1;
+                }
+                let mut width = 1;
+                while a + width < S
+                    && mask[(a + width) * S + b..(a + width) * S + b + height]
+                        .iter()
+                        .all(|&f| f == face)
+                {
+                    width += 1;
+                }
+
+                for i in a..a + width {
+                    mask[i * S + b..i * S + b + height].fill(0);
+                }
+                self.quad(axis, k, [a, b], [width, height], origin);
+                b += height;
+            }
+        }
+    }
+
+    fn quad(
+        &mut self,
+        axis: usize,
+        k: usize,
+        start: [usize; 2],
+        size: [usize; 2],
+        origin: BlockPos,
+    ) {
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        let corner = |du: usize, dv: usize| {
+            let mut pos = [0.0; 3];
+            pos[axis] = (origin[axis] + k as i32) as f32;
+            pos[u] = (origin[u] + (start[0] + du) as i32) as f32;
+            pos[v] = (origin[v] + (start[1] + dv) as i32) as f32;
+            Vertex {
+                pos,
+                axis: axis as u32,
+            }
+        };
+
+        let i = self.vertices.len() as u32;
+        self.vertices.extend([
+            corner(0, 0),
+            corner(size[0], 0),
+            corner(0, size[1]),
+            corner(size[0], size[1]),
+        ]);
+        self.indices.extend([i, i + 1, i + 2, i + 1, i + 3, i + 2]);
+    }
+}
+
+fn padded_index(p: [usize; 3]) -> usize {
+    (p[0] * PADDED + p[1]) * PADDED + p[2]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::generator::FlatGenerator;
+
+    const NO_NEIGHBOURS: Neighbours = Neighbours {
+        low: [false; 3],
+        high: [false; 3],
+    };
+
+    fn storage() -> ChunkStorage {
+        ChunkStorage::new(FlatGenerator::new(-1000))
+    }
+
+    // Unit faces covered by the quads as their axis and minimum corner
+    fn faces(mesh: &ChunkMesh, faces: &mut HashSet<(usize, [i32; 3])>) {
+        for quad in mesh.vertices.chunks_exact(4) {
+            let axis = quad[0].axis as usize;
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+            let (min, max) = (quad[0].pos, quad[3].pos);
+            for a in min[u] as i32..max[u] as i32 {
+                for b in min[v] as i32..max[v] as i32 {
+                    let mut p = [0; 3];
+                    (p[axis], p[u], p[v]) = (min[axis] as i32, a, b);
+                    assert!(faces.insert((axis, p)), "duplicate face {axis} {p:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn single_voxel_has_six_quads() {
+        let mut chunks = storage();
+        chunks.set_block(3, 4, 5, block::STONE);
+        let (pos, _) = chunk_storage::chunk_of(BlockPos::new(3, 4, 5));
+        let mesh = ChunkMesh::build(&chunks, pos, NO_NEIGHBOURS);
+        assert_eq!(mesh.vertices.len(), 6 * 4);
+        assert_eq!(mesh.indices.len(), 6 * 6);
+
+        let mut found = HashSet::new();
+        faces(&mesh, &mut found);
+        let expected: HashSet<_> = (0..3)
+            .flat_map(|axis| {
+                let mut high = [3, 4, 5];
+                high[axis] += 1;
+                [(axis, [3, 4, 5]), (axis, high)]
+            })
+            .collect();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn full_chunk_merges_into_six_quads() {
+        let mut chunks = storage();
+        let pos = ChunkPos::new(1, 2, 3);
+        chunks.insert(pos.x, pos.y, pos.z, Chunk::filled(block::STONE));
+        let mesh = ChunkMesh::build(&chunks, pos, NO_NEIGHBOURS);
+        assert_eq!(mesh.vertices.len(), 6 * 4);
+
+        let mut found = HashSet::new();
+        faces(&mesh, &mut found);
+        assert_eq!(found.len(), 6 * S * S);
+    }
+
+    #[test]
+    fn shared_boundaries_are_meshed_once() {
+        let mut chunks = storage();
+        let origin = ChunkPos::new(0, 2, 0);
+        let size = WindowSize::new(2, 1);
+        // two chunks next to each other along x with something on both sides
+        // of the boundary, the chunks behind them along z stay empty
+        for (i, pos) in [origin, origin + ChunkPos::new(1, 0, 0)]
+            .into_iter()
+            .enumerate()
+        {
+            let mut chunk = Chunk::empty();
+            for x in 0..S {
+                for y in 0..S {
+                    for z in 0..S {
+                        if (x * 7 + y * 3 + z * 5 + i) % 11 < 4 {
+                            chunk.set(x, y, z, block::STONE);
+                        }
+                    }
+                }
+            }
+            chunks.insert(pos.x, pos.y, pos.z, chunk);
+        }
+
+        let mut found = HashSet::new();
+        for pos in size.chunks(origin) {
+            let neighbours = Neighbours::in_window(size, origin, pos);
+            faces(&ChunkMesh::build(&chunks, pos, neighbours), &mut found);
+        }
+
+        // every side of a solid voxel facing air or the outside of the window
+        let min = chunk_storage::chunk_min(origin);
+        let (xz, y) = (size.blocks_xz() as i32, size.blocks_y() as i32);
+        let max = min + BlockPos::new(xz, y, xz);
+        let solid = |p: BlockPos| {
+            (0..3).all(|axis| (min[axis]..max[axis]).contains(&p[axis]))
+                && chunks.get_block(p.x, p.y, p.z) != block::AIR
+        };
+        let mut expected = HashSet::new();
+        for x in min.x..max.x {
+            for y in min.y..max.y {
+                for z in min.z..max.z {
+                    let p = BlockPos::new(x, y, z);
+                    if !solid(p) {
+                        continue;
+                    }
+                    for axis in 0..3 {
+                        for step in [-1, 1] {
+                            let mut next = p;
+                            next[axis] += step;
+                            if !solid(next) {
+                                let mut face = p;
+                                face[axis] += (step + 1) / 2;
+                                expected.insert((axis, face.into()));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        assert_eq!(found, expected);
+    }
+}
@@ -0,0 +1,221 @@
+use cgmath::{InnerSpace, Vector3, Zero};
+
+use crate::block::{self, BlockId};
+use crate::chunk_storage::{BlockPos, ChunkStorage};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    pub block: BlockPos,
+    pub block_id: BlockId,
+    // Face the ray entered through, zero if it started inside the block
+    pub normal: Vector3<i32>,
+    pub distance: f32,
+    // Empty cell in front of the hit face, where a new block would go
+    pub adjacent: BlockPos,
+}
+
+// First solid voxel along the ray within `max_distance`. Voxel p occupies
+// [p, p + 1) in world space and unloaded chunks count as air.
+pub fn raycast(
+    storage: &ChunkStorage,
+    origin: Vector3<f32>,
+    direction: Vector3<f32>,
+    max_distance: f32,
+) -> Option<RayHit> {
+    raycast_with(origin, direction, max_distance, |p| {
+        storage.get_block(p.x, p.y, p.z)
+    })
+}
+
+// Amanatides & Woo voxel traversal over any block lookup
+pub fn raycast_with(
+    origin: Vector3<f32>,
+    direction: Vector3<f32>,
+    max_distance: f32,
+    mut block_at: impl FnMut(BlockPos) -> BlockId,
+) -> Option<RayHit> {
+    let len = direction.magnitude();
+    if !(len > 0.0 && len.is_finite() && max_distance.is_finite()) {
+        return None;
+    }
+    if !(origin.x.is_finite() && origin.y.is_finite() && origin.z.is_finite()) {
+        return None;
+    }
+    let dir = direction / len;
+
+    let step = dir.map(|v| {
+        if v > 0.0 {
+            1
+        } else if v < 0.0 {
+            -1
+        } else {
+            0
+        }
+    });
+    // distance along the ray between two boundaries on each axis
+    let delta = dir.map(|v| {
+        if v == 0.0 {
+            f32::INFINITY
+        } else {
+            1.0 / v.abs()
+        }
+    });
+    // distance along the ray to the next boundary on each axis
+    let boundary = |o: f32, d: f32| {
+        if d > 0.0 {
+            (o.floor() + 1.0 - o) / d
+        } else if d < 0.0 {
+            (o - o.floor()) / -d
+        } else {
+            f32::INFINITY
+        }
+    };
+    let mut next = Vector3::new(
+        boundary(origin.x, dir.x),
+        boundary(origin.y, dir.y),
+        boundary(origin.z, dir.z),
+    );
+
+    let mut cell = origin.map(|v| v.floor() as i32);
+    let mut normal = Vector3::zero();
+    let mut distance = 0.0;
+    loop {
+        let block_id = block_at(cell);
+        if block_id != block::AIR {
+            return Some(RayHit {
+                block: cell,
+                block_id,
+                normal,
+                distance,
+                adjacent: cell + normal,
+            });
+        }
+
+        let axis = if next.x < next.y {
+            if next.x < next.z {
+                0
+            } else {
+                2
+            }
+        } else if next.y < next.z {
+            1
+        } else {
+            2
+        };
+        distance = next[axis];
+        if distance > max_distance {
+            return None;
+        }
+        cell[axis] += step[axis];
+        next[axis] += delta[axis];
+        normal = Vector3::zero();
+        normal[axis] = -step[axis];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::FlatGenerator;
+
+    fn storage(blocks: &[BlockPos]) -> ChunkStorage {
+        // ground far below everything the tests look at
+        let mut storage = ChunkStorage::new(FlatGenerator::new(-1000));
+        for p in blocks {
+            storage.set_block(p.x, p.y, p.z, block::BRICK);
+        }
+        storage
+    }
+
+    #[test]
+    fn axis_aligned_hit() {
+        let storage = storage(&[BlockPos::new(5, 10, 2)]);
+        let hit = raycast(
+            &storage,
+            Vector3::new(0.5, 10.5, 2.5),
+            Vector3::new(1.0, 0.0, 0.0),
+            10.0,
+        )
+        .unwrap();
+        assert_eq!(hit.block, BlockPos::new(5, 10, 2));
+        assert_eq!(hit.block_id, block::BRICK);
+        assert_eq!(hit.normal, Vector3::new(-1, 0, 0));
+        assert_eq!(hit.distance, 4.5);
+        assert_eq!(hit.adjacent, BlockPos::new(4, 10, 2));
+    }
+
+    #[test]
+    fn diagonal_hit_across_chunks() {
+        // starts in the chunk below x = 0 and z = 0 and enters the block
+        // through its low z face
+        let storage = storage(&[BlockPos::new(1, 10, 1)]);
+        let hit = raycast(
+            &storage,
+            Vector3::new(-2.3, 10.5, -2.7),
+            Vector3::new(1.0, 0.0, 1.0),
+            10.0,
+        )
+        .unwrap();
+        assert_eq!(hit.block, BlockPos::new(1, 10, 1));
+        assert_eq!(hit.normal, Vector3::new(0, 0, -1));
+        assert_eq!(hit.adjacent, BlockPos::new(1, 10, 0));
+        assert!((hit.distance - 3.7 * std::f32::consts::SQRT_2).abs() < 1e-4);
+    }
+
+    #[test]
+    fn negative_coordinates_round_down() {
+        let storage = storage(&[BlockPos::new(-40, -20, -70)]);
+        let hit = raycast(
+            &storage,
+            Vector3::new(-35.5, -19.5, -69.5),
+            Vector3::new(-2.0, 0.0, 0.0),
+            10.0,
+        )
+        .unwrap();
+        assert_eq!(hit.block, BlockPos::new(-40, -20, -70));
+        assert_eq!(hit.normal, Vector3::new(1, 0, 0));
+        assert_eq!(hit.distance, 3.5);
+        assert_eq!(hit.adjacent, BlockPos::new(-39, -20, -70));
+    }
+
+    #[test]
+    fn blocks_beyond_max_distance_are_missed() {
+        let storage = storage(&[BlockPos::new(5, 10, 2)]);
+        let cast = |max_distance| {
+            raycast(
+                &storage,
+                Vector3::new(0.5, 10.5, 2.5),
+                Vector3::new(1.0, 0.0, 0.0),
+                max_distance,
+            )
+        };
+        assert_eq!(cast(4.4), None);
+        assert!(cast(4.5).is_some());
+        // nothing at all along the ray
+        assert_eq!(
+            raycast(
+                &storage,
+                Vector3::new(0.5, 10.5, 2.5),
+                Vector3::new(0.0, 1.0, 0.0),
+                100.0
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn ray_starting_inside_a_block() {
+        let storage = storage(&[BlockPos::new(5, 10, 2)]);
+        let hit = raycast(
+            &storage,
+            Vector3::new(5.5, 10.5, 2.5),
+            Vector3::new(0.0, -1.0, 0.0),
+            10.0,
+        )
+        .unwrap();
+        assert_eq!(hit.block, BlockPos::new(5, 10, 2));
+        assert_eq!(hit.normal, Vector3::zero());
+        assert_eq!(hit.distance, 0.0);
+        assert_eq!(hit.adjacent, hit.block);
+    }
+}
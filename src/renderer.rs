@@ -0,0 +1,1540 @@
+use std::collections::{HashMap, HashSet};
+
+use cgmath::{InnerSpace, SquareMatrix};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    background,
+    block::BlockRegistry,
+    block_textures::BlockTextures,
+    brickmap::{self, Brickmap},
+    camera::Camera,
+    chunk,
+    chunk_storage::{self, ChunkStorage, WindowSize},
+    globals::{self, GlobalsUniform},
+    lattice,
+    mesh::{ChunkMesh, Neighbours},
+    raycast::RayHit,
+    shaders::{ShaderFiles, ShaderSources},
+    shadow,
+    vertex::{self, Vertex},
+};
+
+struct Pipelines {
+    main: wgpu::RenderPipeline,
+    background: wgpu::RenderPipeline,
+    crosshair: wgpu::RenderPipeline,
+    shadow: wgpu::RenderPipeline,
+    mesh: wgpu::RenderPipeline,
+    mesh_shadow: wgpu::RenderPipeline,
+    ray_march: wgpu::RenderPipeline,
+}
+
+// Chunk mesh on the GPU, along with the neighbours it was built for
+struct MeshBuffers {
+    neighbours: Neighbours,
+    // empty meshes have no buffers
+    buffers: Option<(wgpu::Buffer, wgpu::Buffer)>,
+    index_format: wgpu::IndexFormat,
+    index_count: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+    // every plane of the window, empty fragments are discarded
+    Lattice,
+    // greedy-meshed faces of every chunk in the window
+    Mesh,
+    // one ray per pixel through the chunk texture, with traced shadows
+    RayMarch,
+}
+
+// Everything needed to draw the world into a colour target of `format`,
+// independent of where that target comes from.
+pub struct Renderer {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+
+    pipelines: Pipelines,
+    shader_files: ShaderFiles,
+
+    bind_group_layout: wgpu::BindGroupLayout,
+    shadow_bind_group_layout: wgpu::BindGroupLayout,
+    background_bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    background_bind_group: wgpu::BindGroup,
+    shadow_bind_group: wgpu::BindGroup,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    texture_bind_group: wgpu::BindGroup,
+    texture_sampler: wgpu::Sampler,
+
+    vertex_buf: wgpu::Buffer,
+    index_buf: wgpu::Buffer,
+    index_format: wgpu::IndexFormat,
+    index_count: u32,
+    depth_texture: wgpu::Texture,
+    multisample_texture: wgpu::Texture,
+    background_vertices: wgpu::Buffer,
+    globals_buf: wgpu::Buffer,
+    chunk_texture: wgpu::Texture,
+    occupancy_texture: wgpu::Texture,
+    brick_texture: wgpu::Texture,
+    block_colors_buf: wgpu::Buffer,
+    block_colors_version: u64,
+    background_buf: wgpu::Buffer,
+    shadow_map: wgpu::Texture,
+
+    // window currently held by the chunk texture
+    window_size: WindowSize,
+    window_origin: Option<chunk_storage::ChunkPos>,
+    // CPU copy of the brick texture
+    bricks: Brickmap,
+    // index ranges of the lattice planes next to any non-empty brick
+    lattice_ranges: Vec<std::ops::Range<u32>>,
+
+    // chunk meshes of the window, only kept in mesh mode
+    meshes: HashMap<chunk_storage::ChunkPos, MeshBuffers>,
+    // chunks whose mesh has to be rebuilt because they or a neighbour changed
+    stale_meshes: HashSet<chunk_storage::ChunkPos>,
+
+    pub mode: RenderMode,
+    // meshes rebuilt by one `upload_chunks`, the rest wait for later calls
+    pub mesh_builds_per_frame: usize,
+
+    // direction the light travels in, and where the sky draws the sun
+    pub light_dir: cgmath::Vector3<f32>,
+    pub sun_dir: cgmath::Vector3<f32>,
+    pub light_color: cgmath::Vector3<f32>,
+    pub ambient_color: cgmath::Vector3<f32>,
+    // exponential height fog
+    pub fog: bool,
+    pub fog_color: cgmath::Vector3<f32>,
+    pub fog_density: f32,
+    pub fog_height: f32,
+    pub fog_height_falloff: f32,
+    // strength of sunlight scattered towards the camera by the fog
+    pub fog_scattering: f32,
+    pub grid_lines: bool,
+    pub crosshair: bool,
+    pub ao: bool,
+    // 0 disables ambient occlusion, 1 makes fully enclosed corners black
+    pub ao_strength: f32,
+    // PCF kernel is (2 * radius + 1)² samples
+    pub shadow_pcf_radius: u32,
+    // depth bias in light space and offset along the normal in world units
+    pub shadow_bias: f32,
+    pub shadow_normal_bias: f32,
+    // voxel to highlight, usually the one under the crosshair
+    pub target: Option<RayHit>,
+}
+
+impl Renderer {
+    pub const MAX_PCF_RADIUS: u32 = 4;
+    pub const MESH_BUILDS_PER_FRAME: usize = 8;
+
+    pub fn new(
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+        block_types: &BlockRegistry,
+    ) -> Self {
+        let globals_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("view matrix buffer"),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            size: std::mem::size_of::<globals::GlobalsUniform>() as u64,
+            mapped_at_creation: false,
+        });
+
+        let block_colors_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("block colors buffer"),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            contents: bytemuck::bytes_of(&block_types.colors()),
+        });
+
+        let window_size = WindowSize::DEFAULT;
+        let chunk_texture = Self::create_chunk_texture(&device, window_size);
+        let occupancy_texture = Self::create_occupancy_texture(&device, window_size);
+        let brick_texture = Self::create_brick_texture(&device, window_size);
+
+        let shadow_map = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("shadow map texture"),
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Depth32Float,
+            size: wgpu::Extent3d {
+                width: shadow::MAP_SIZE,
+                height: shadow::MAP_SIZE,
+                depth_or_array_layers: shadow::CASCADE_COUNT as u32,
+            },
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            mip_level_count: 1,
+            sample_count: 1,
+            view_formats: &[],
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        sample_type: wgpu::TextureSampleType::Uint,
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                    },
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        sample_type: wgpu::TextureSampleType::Uint,
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                    },
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        sample_type: wgpu::TextureSampleType::Uint,
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                    },
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    count: None,
+                },
+            ],
+        });
+
+        let bind_group = Self::create_bind_group(
+            &device,
+            &bind_group_layout,
+            &globals_buf,
+            &chunk_texture,
+            &occupancy_texture,
+            &brick_texture,
+            &block_colors_buf,
+        );
+
+        let shadow_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("shadow bind group layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            sample_type: wgpu::TextureSampleType::Depth,
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
+                        },
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        count: None,
+                    },
+                ],
+            });
+
+        // linear filtering makes every comparison a bilinear 2x2 lookup
+        let shadow_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("shadow sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let shadow_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("shadow bind group"),
+            layout: &shadow_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&shadow_map.create_view(
+                        &wgpu::TextureViewDescriptor {
+                            usage: Some(
+                                wgpu::TextureUsages::RENDER_ATTACHMENT
+                                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                            ),
+                            dimension: Some(wgpu::TextureViewDimension::D2Array),
+                            ..Default::default()
+                        },
+                    )),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&shadow_sampler),
+                },
+            ],
+        });
+
+        let texture_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("block texture bind group layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
+                        },
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        count: None,
+                    },
+                ],
+            });
+
+        // pixelated up close, filtered in the distance
+        let texture_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("block texture sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::MipmapFilterMode::Linear,
+            ..Default::default()
+        });
+        let texture_bind_group = Self::create_texture_bind_group(
+            &device,
+            &queue,
+            &texture_bind_group_layout,
+            &texture_sampler,
+            &BlockTextures::default(),
+        );
+
+        let (background_buf, background_bind_group_layout, background_bind_group) =
+            background::background(&device);
+
+        let shader_files = ShaderFiles::new();
+        let create_pipelines = |sources: &ShaderSources| {
+            Self::create_pipelines(
+                &device,
+                format,
+                &bind_group_layout,
+                &shadow_bind_group_layout,
+                &texture_bind_group_layout,
+                &background_bind_group_layout,
+                sources,
+            )
+        };
+        let pipelines = create_pipelines(&shader_files.load()).unwrap_or_else(|e| {
+            log::error!("Falling back to the embedded shaders:\n{e}");
+            create_pipelines(&ShaderFiles::embedded()).expect("embedded shaders are invalid")
+        });
+
+        let (vertex_buf, index_buf, index_format, index_count) =
+            Self::create_lattice_buffers(&device, window_size);
+
+        let depth_texture = Self::create_depth_texture(&device, width, height);
+        let multisample_texture = Self::create_multisample_texture(&device, format, width, height);
+
+        let background_vertices = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("background vertices"),
+            usage: wgpu::BufferUsages::VERTEX,
+            contents: bytemuck::cast_slice(&[
+                [-1.0f32, -1.0, 0.0],
+                [-1.0, 1.0, 0.0],
+                [1.0, -1.0, 0.0],
+                [-1.0, 1.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [1.0, -1.0, 0.0],
+            ]),
+        });
+
+        Self {
+            block_colors_version: block_types.version(),
+
+            device,
+            queue,
+            format,
+            width,
+            height,
+
+            pipelines,
+            shader_files,
+
+            bind_group_layout,
+            shadow_bind_group_layout,
+            background_bind_group_layout,
+            bind_group,
+            background_bind_group,
+            shadow_bind_group,
+            texture_bind_group_layout,
+            texture_bind_group,
+            texture_sampler,
+
+            vertex_buf,
+            index_buf,
+            index_format,
+            index_count,
+            depth_texture,
+            multisample_texture,
+            background_vertices,
+            globals_buf,
+            chunk_texture,
+            occupancy_texture,
+            brick_texture,
+            block_colors_buf,
+            background_buf,
+            shadow_map,
+
+            window_size,
+            window_origin: None,
+            bricks: Brickmap::new(window_size),
+            lattice_ranges: Vec::new(),
+
+            meshes: HashMap::new(),
+            stale_meshes: HashSet::new(),
+
+            mode: RenderMode::Lattice,
+            mesh_builds_per_frame: Self::MESH_BUILDS_PER_FRAME,
+
+            light_dir: cgmath::Vector3::new(0.0, -1.0, 0.0),
+            sun_dir: cgmath::Vector3::new(0.0, 1.0, 0.0),
+            light_color: cgmath::Vector3::new(1.0, 1.0, 1.0),
+            ambient_color: cgmath::Vector3::new(0.3, 0.3, 0.3),
+            fog: true,
+            fog_color: cgmath::Vector3::new(0.7, 0.82, 0.95),
+            fog_density: 0.008,
+            fog_height: 0.0,
+            fog_height_falloff: 0.08,
+            fog_scattering: 0.5,
+            grid_lines: false,
+            crosshair: false,
+            ao: true,
+            ao_strength: 0.6,
+            shadow_pcf_radius: 1,
+            shadow_bias: 0.0005,
+            shadow_normal_bias: 0.05,
+            target: None,
+        }
+    }
+
+    pub fn device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.format
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    // Rebuilds the pipelines if a shader file changed. On errors the old
+    // pipelines stay in use.
+    pub fn poll_shaders(&mut self) {
+        if self.shader_files.changed() {
+            self.reload_shaders();
+        }
+    }
+
+    pub fn reload_shaders(&mut self) {
+        let pipelines = Self::create_pipelines(
+            &self.device,
+            self.format,
+            &self.bind_group_layout,
+            &self.shadow_bind_group_layout,
+            &self.texture_bind_group_layout,
+            &self.background_bind_group_layout,
+            &self.shader_files.load(),
+        );
+        match pipelines {
+            Ok(pipelines) => {
+                self.pipelines = pipelines;
+                log::info!("Reloaded shaders");
+            }
+            Err(e) => log::error!("Keeping the previous shaders:\n{e}"),
+        }
+    }
+
+    pub fn window_size(&self) -> WindowSize {
+        self.window_size
+    }
+
+    // Resizes the chunk texture and lattice, every chunk is uploaded again
+    // by the next `upload_chunks`.
+    pub fn set_window_size(&mut self, size: WindowSize) {
+        if size == self.window_size {
+            return;
+        }
+        self.window_size = size;
+        self.window_origin = None;
+        self.chunk_texture = Self::create_chunk_texture(&self.device, size);
+        self.occupancy_texture = Self::create_occupancy_texture(&self.device, size);
+        self.brick_texture = Self::create_brick_texture(&self.device, size);
+        self.bricks = Brickmap::new(size);
+        self.lattice_ranges.clear();
+        self.bind_group = Self::create_bind_group(
+            &self.device,
+            &self.bind_group_layout,
+            &self.globals_buf,
+            &self.chunk_texture,
+            &self.occupancy_texture,
+            &self.brick_texture,
+            &self.block_colors_buf,
+        );
+        (
+            self.vertex_buf,
+            self.index_buf,
+            self.index_format,
+            self.index_count,
+        ) = Self::create_lattice_buffers(&self.device, size);
+    }
+
+    pub fn set_block_textures(&mut self, textures: &BlockTextures) {
+        self.texture_bind_group = Self::create_texture_bind_group(
+            &self.device,
+            &self.queue,
+            &self.texture_bind_group_layout,
+            &self.texture_sampler,
+            textures,
+        );
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.width = width;
+        self.height = height;
+        self.multisample_texture =
+            Self::create_multisample_texture(&self.device, self.format, width, height);
+        self.depth_texture = Self::create_depth_texture(&self.device, width, height);
+    }
+
+    // Uploads block colours and every chunk that changed or entered the
+    // window around the camera since the last call. The window takes the
+    // storage's size.
+    pub fn upload_chunks(&mut self, chunks: &mut ChunkStorage, camera: &Camera) {
+        self.set_window_size(chunks.window_size());
+
+        let block_types = chunks.block_types();
+        if block_types.version() != self.block_colors_version {
+            self.block_colors_version = block_types.version();
+            self.queue.write_buffer(
+                &self.block_colors_buf,
+                0,
+                bytemuck::bytes_of(&block_types.colors()),
+            );
+        }
+
+        // Entering chunks are uploaded even if they are still generating, so
+        // their slot does not show what the window left behind.
+        let size = self.window_size;
+        let window_origin = size.around(camera.position());
+        let mut uploads = chunks.take_dirty();
+        if self.window_origin != Some(window_origin) {
+            uploads.extend(size.chunks(window_origin).filter(|&pos| {
+                self.window_origin
+                    .is_none_or(|origin| !size.contains(origin, pos))
+            }));
+            self.window_origin = Some(window_origin);
+        }
+        let mut uploaded = false;
+        for &pos in &uploads {
+            if size.contains(window_origin, pos) {
+                self.upload_chunk(chunks, pos);
+                uploaded = true;
+            }
+        }
+        if uploaded {
+            self.update_lattice_ranges(window_origin);
+        }
+
+        // meshes read the layer of their low neighbours next to them
+        for pos in uploads {
+            self.stale_meshes.extend(
+                [(0, 0, 0), (1, 0, 0), (0, 1, 0), (0, 0, 1)]
+                    .map(|(x, y, z)| pos + chunk_storage::ChunkPos::new(x, y, z)),
+            );
+        }
+        self.update_meshes(chunks, window_origin);
+    }
+
+    // Leaves out the lattice planes that only border empty bricks, they
+    // cannot have a visible face or cast a shadow.
+    fn update_lattice_ranges(&mut self, window_origin: chunk_storage::ChunkPos) {
+        let [wrap_x, wrap_z, wrap_y] = self.window_size.texture_slot(window_origin);
+        let xz = self.window_size.blocks_xz();
+        let brick = brickmap::BRICK_SIZE as u32;
+        self.lattice_ranges.clear();
+        for (axis, lattice_axis, len) in [
+            (0, vertex::AXIS_X, xz),
+            (2, vertex::AXIS_Z, xz),
+            (1, vertex::AXIS_Y, self.window_size.blocks_y()),
+        ] {
+            let layers = self.bricks.occupied_layers(axis, [wrap_x, wrap_y, wrap_z]);
+            let occupied = |layer: u32| layers[layer as usize];
+            for i in 0..=len {
+                if !((i > 0 && occupied((i - 1) / brick)) || (i < len && occupied(i / brick))) {
+                    continue;
+                }
+                let range = lattice::Lattice::plane_indices(xz, lattice_axis, i);
+                // planes are next to each other in the index buffer
+                match self.lattice_ranges.last_mut() {
+                    Some(last) if last.end == range.start => last.end = range.end,
+                    _ => self.lattice_ranges.push(range),
+                }
+            }
+        }
+    }
+
+    // Rebuilds the meshes of stale chunks and of chunks whose neighbours
+    // entered or left the window, closest to the camera first and at most
+    // `mesh_builds_per_frame` of them. Outside of mesh mode they are dropped.
+    fn update_meshes(&mut self, chunks: &ChunkStorage, window_origin: chunk_storage::ChunkPos) {
+        if self.mode != RenderMode::Mesh {
+            self.meshes.clear();
+            self.stale_meshes.clear();
+            return;
+        }
+
+        let size = self.window_size;
+        self.meshes
+            .retain(|&pos, _| size.contains(window_origin, pos));
+        self.stale_meshes
+            .retain(|&pos| size.contains(window_origin, pos));
+        let mut builds: Vec<_> = size
+            .chunks(window_origin)
+            .map(|pos| (pos, Neighbours::in_window(size, window_origin, pos)))
+            .filter(|(pos, neighbours)| {
+                self.stale_meshes.contains(pos)
+                    || self
+                        .meshes
+                        .get(pos)
+                        .is_none_or(|mesh| mesh.neighbours != *neighbours)
+            })
+            .collect();
+        builds.sort_by_key(|&(pos, _)| size.centre_distance(window_origin, pos));
+
+        for (pos, neighbours) in builds.into_iter().take(self.mesh_builds_per_frame) {
+            self.stale_meshes.remove(&pos);
+            let mesh = ChunkMesh::build(chunks, pos, neighbours);
+            let (indices, index_format) = mesh.index_data();
+            let buffers = (!mesh.is_empty()).then(|| {
+                let vertex_buf =
+                    self.device
+                        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                            label: Some("chunk mesh vertex buffer"),
+                            usage: wgpu::BufferUsages::VERTEX,
+                            contents: bytemuck::cast_slice(&mesh.vertices),
+                        });
+                let index_buf = self
+                    .device
+                    .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                        label: Some("chunk mesh index buffer"),
+                        usage: wgpu::BufferUsages::INDEX,
+                        contents: &indices,
+                    });
+                (vertex_buf, index_buf)
+            });
+            self.meshes.insert(
+                pos,
+                MeshBuffers {
+                    neighbours,
+                    buffers,
+                    index_format,
+                    index_count: mesh.indices.len() as u32,
+                },
+            );
+        }
+    }
+
+    // Draws the world geometry of the current mode with the instance range,
+    // the shadow pipelines take the cascade from it.
+    fn draw_world<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        instances: std::ops::Range<u32>,
+    ) {
+        match self.mode {
+            RenderMode::Lattice => {
+                render_pass.set_vertex_buffer(0, self.vertex_buf.slice(..));
+                render_pass.set_index_buffer(self.index_buf.slice(..), self.index_format);
+                for range in &self.lattice_ranges {
+                    render_pass.draw_indexed(range.clone(), 0, instances.clone());
+                }
+            }
+            // a fullscreen quad, the shader finds the voxels itself
+            RenderMode::RayMarch => {
+                render_pass.set_vertex_buffer(0, self.background_vertices.slice(..));
+                render_pass.draw(0..6, instances);
+            }
+            RenderMode::Mesh => {
+                for mesh in self.meshes.values() {
+                    if let Some((vertex_buf, index_buf)) = &mesh.buffers {
+                        render_pass.set_vertex_buffer(0, vertex_buf.slice(..));
+                        render_pass.set_index_buffer(index_buf.slice(..), mesh.index_format);
+                        render_pass.draw_indexed(0..mesh.index_count, 0, instances.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    // Draws the background, shadow and main passes into `target`, which
+    // must have the renderer's format and size.
+    pub fn render(&self, target: &wgpu::TextureView, camera: &Camera, millis_elapsed: u32) {
+        let fog = self.fog_uniform(camera);
+        self.queue.write_buffer(
+            &self.background_buf,
+            0,
+            bytemuck::bytes_of(&background::BackgroundUniform {
+                resolution: [self.width, self.height],
+                millis_elapsed,
+                pitch: camera.pitch.0,
+                yaw: camera.yaw.0,
+                fovy: camera.fovy.0,
+                sun_dir: self.sun_dir.normalize().into(),
+                fog,
+                crosshair: self.crosshair as u32,
+                _pad0: 0,
+                _pad1: 0,
+            }),
+        );
+
+        let window_origin = self
+            .window_origin
+            .unwrap_or_else(|| self.window_size.around(camera.position()));
+        let window_offset = chunk_storage::chunk_min(window_origin);
+        let [wrap_x, wrap_z, wrap_y] = self.window_size.texture_slot(window_origin);
+        let corners = lattice::corners(self.window_size.blocks_xz(), self.window_size.blocks_y())
+            .map(|corner| corner + window_offset.cast().unwrap());
+
+        let cam_mat = camera.proj_view_matrix();
+        let dir = camera.direction();
+        let pos = camera.position();
+        let light = self.light_dir.normalize();
+        let cascades = shadow::cascades(camera, light, &corners);
+        let (target_voxel, target_normal) = self.target.map_or(([0; 3], [0; 3]), |hit| {
+            (hit.block.into(), hit.normal.into())
+        });
+        let globals = GlobalsUniform {
+            proj_view_mat: cam_mat.into(),
+            inv_proj_view_mat: cam_mat
+                .invert()
+                .unwrap_or_else(cgmath::Matrix4::identity)
+                .into(),
+            light_mats: cascades.matrices.map(Into::into),
+            cam_dir: [dir.x, dir.y, dir.z],
+            cam_pos: [pos.x, pos.y, pos.z],
+            light_dir: [light.x, light.y, light.z],
+            grid_lines: self.grid_lines as u32,
+            window_offset: window_offset.into(),
+            window_wrap: [wrap_x, wrap_y, wrap_z],
+            has_target: self.target.is_some() as u32,
+            target_voxel,
+            target_normal,
+            resolution: [self.width, self.height],
+            ao_enabled: self.ao as u32,
+            ao_strength: self.ao_strength.clamp(0.0, 1.0),
+            shadow_bias: self.shadow_bias,
+            shadow_normal_bias: self.shadow_normal_bias,
+            shadow_pcf_radius: self.shadow_pcf_radius.min(Self::MAX_PCF_RADIUS) as i32,
+            _pad0: 0,
+            _pad1: 0,
+            _pad2: 0,
+            traced_shadows: (self.mode == RenderMode::RayMarch) as u32,
+            _pad3: 0,
+            cascade_splits: cascades.splits,
+            light_color: self.light_color.into(),
+            ambient_color: self.ambient_color.into(),
+            fog,
+            _pad4: 0,
+            _pad5: 0,
+            _pad6: 0,
+        };
+        self.queue
+            .write_buffer(&self.globals_buf, 0, bytemuck::cast_slice(&[globals]));
+
+        let multisample_view = self.multisample_texture.create_view(&Default::default());
+        let depth_view = self.depth_texture.create_view(&Default::default());
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("command encoder"),
+            });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("background render pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color {
+                            r: 0.5,
+                            g: 0.0,
+                            b: 0.5,
+                            a: 1.0,
+                        }),
+                        store: wgpu::StoreOp::Store,
+                    },
+                    resolve_target: None,
+                    view: &multisample_view,
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+                multiview_mask: None,
+            });
+            render_pass.set_pipeline(&self.pipelines.background);
+            render_pass.set_bind_group(0, &self.background_bind_group, &[]);
+            render_pass.set_vertex_buffer(0, self.background_vertices.slice(..));
+            render_pass.draw(0..6, 0..1);
+        }
+        // one pass per cascade, the instance index picks its light matrix
+        let shadow_passes = match self.mode {
+            RenderMode::RayMarch => 0,
+            _ => shadow::CASCADE_COUNT as u32,
+        };
+        for cascade in 0..shadow_passes {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("shadow render pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.shadow_map.create_view(&wgpu::TextureViewDescriptor {
+                        usage: Some(wgpu::TextureUsages::RENDER_ATTACHMENT),
+                        dimension: Some(wgpu::TextureViewDimension::D2),
+                        base_array_layer: cascade,
+                        array_layer_count: Some(1),
+                        ..Default::default()
+                    }),
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+                multiview_mask: None,
+            });
+            render_pass.set_pipeline(match self.mode {
+                RenderMode::Mesh => &self.pipelines.mesh_shadow,
+                _ => &self.pipelines.shadow,
+            });
+            render_pass.set_bind_group(0, &self.bind_group, &[]);
+            self.draw_world(&mut render_pass, cascade..cascade + 1);
+        }
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("main render pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Discard,
+                    },
+                    resolve_target: Some(target),
+                    view: &multisample_view,
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &depth_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+                multiview_mask: None,
+            });
+            render_pass.set_pipeline(match self.mode {
+                RenderMode::Lattice => &self.pipelines.main,
+                RenderMode::Mesh => &self.pipelines.mesh,
+                RenderMode::RayMarch => &self.pipelines.ray_march,
+            });
+            render_pass.set_bind_group(0, &self.bind_group, &[]);
+            render_pass.set_bind_group(1, &self.shadow_bind_group, &[]);
+            render_pass.set_bind_group(2, &self.texture_bind_group, &[]);
+            self.draw_world(&mut render_pass, 0..1);
+
+            if self.crosshair {
+                render_pass.set_pipeline(&self.pipelines.crosshair);
+                render_pass.set_bind_group(0, &self.background_bind_group, &[]);
+                render_pass.set_vertex_buffer(0, self.background_vertices.slice(..));
+                render_pass.draw(0..6, 0..1);
+            }
+        }
+
+        self.queue.submit([encoder.finish()]);
+    }
+
+    fn fog_uniform(&self, camera: &Camera) -> globals::FogUniform {
+        let light_to = -self.light_dir.normalize();
+        globals::FogUniform {
+            color: self.fog_color.into(),
+            // zero density turns fog off in the shaders
+            density: if self.fog {
+                self.fog_density.max(0.0)
+            } else {
+                0.0
+            },
+            scatter_color: (self.light_color * self.fog_scattering.max(0.0)).into(),
+            height_falloff: self.fog_height_falloff,
+            light_to: light_to.into(),
+            height: self.fog_height,
+            cam_height: camera.position.y,
+            far: camera.zfar,
+            _pad: [0; 2],
+        }
+    }
+
+    fn upload_chunk(&mut self, chunks: &ChunkStorage, pos: chunk_storage::ChunkPos) {
+        let [x, y, z] = self.window_size.texture_slot(pos);
+        self.queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: &self.chunk_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z },
+                aspect: wgpu::TextureAspect::All,
+            },
+            &*chunks.copy_to_render_buffer(pos.x, pos.y, pos.z),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(chunk::CHUNK_SIZE as u32),
+                rows_per_image: Some(chunk::CHUNK_SIZE as u32),
+            },
+            wgpu::Extent3d {
+                width: chunk::CHUNK_SIZE as u32,
+                height: chunk::CHUNK_SIZE as u32,
+                depth_or_array_layers: chunk::CHUNK_SIZE as u32,
+            },
+        );
+        // a single layer of column words
+        self.queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: &self.occupancy_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x,
+                    y,
+                    z: z / chunk::CHUNK_SIZE as u32,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            bytemuck::cast_slice(&*chunks.copy_to_occupancy_buffer(pos.x, pos.y, pos.z)),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(chunk::CHUNK_SIZE as u32 * 4),
+                rows_per_image: Some(chunk::CHUNK_SIZE as u32),
+            },
+            wgpu::Extent3d {
+                width: chunk::CHUNK_SIZE as u32,
+                height: chunk::CHUNK_SIZE as u32,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        let bricks = chunks.copy_to_brick_buffer(pos.x, pos.y, pos.z);
+        self.bricks.set_chunk([x, y, z], &bricks);
+        let brick = brickmap::BRICK_SIZE as u32;
+        let chunk_bricks = brickmap::CHUNK_BRICKS as u32;
+        self.queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: &self.brick_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: x / brick,
+                    y: y / brick,
+                    z: z / brick,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            &bricks,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(chunk_bricks),
+                rows_per_image: Some(chunk_bricks),
+            },
+            wgpu::Extent3d {
+                width: chunk_bricks,
+                height: chunk_bricks,
+                depth_or_array_layers: chunk_bricks,
+            },
+        );
+    }
+
+    // Builds every pipeline from `sources`. Shader and pipeline errors are
+    // returned instead of ending up in the device's error handler, which
+    // would panic.
+    #[allow(clippy::too_many_arguments)]
+    fn create_pipelines(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        shadow_bind_group_layout: &wgpu::BindGroupLayout,
+        texture_bind_group_layout: &wgpu::BindGroupLayout,
+        background_bind_group_layout: &wgpu::BindGroupLayout,
+        sources: &ShaderSources,
+    ) -> Result<Pipelines, String> {
+        let scope = device.push_error_scope(wgpu::ErrorFilter::Validation);
+
+        let background_shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("background shader module"),
+            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(
+                sources.background.as_str(),
+            )),
+        });
+
+        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("main shader module"),
+            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(sources.main.as_str())),
+        });
+
+        // lattice planes and chunk meshes only differ in the vertex stage
+        let main_pipeline = |vertex_entry: &str| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("render pipeline"),
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: wgpu::TextureFormat::Depth32Float,
+                    depth_compare: Some(wgpu::CompareFunction::Less),
+                    depth_write_enabled: Some(true),
+                    bias: Default::default(),
+                    stencil: Default::default(),
+                }),
+                layout: Some(
+                    &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                        label: Some("pipeline layout descriptor"),
+                        bind_group_layouts: &[
+                            Some(bind_group_layout),
+                            Some(shadow_bind_group_layout),
+                            Some(texture_bind_group_layout),
+                        ],
+                        ..Default::default()
+                    }),
+                ),
+                multisample: wgpu::MultisampleState {
+                    count: 4,
+                    mask: 0xffffffff,
+                    alpha_to_coverage_enabled: false,
+                },
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Cw,
+                    cull_mode: None,
+                    unclipped_depth: false,
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    conservative: false,
+                },
+                vertex: wgpu::VertexState {
+                    buffers: &[wgpu::VertexBufferLayout {
+                        step_mode: Default::default(),
+                        attributes: &Vertex::attributes(),
+                        array_stride: Vertex::stride(),
+                    }],
+                    compilation_options: Default::default(),
+                    entry_point: Some(vertex_entry),
+                    module: &shader_module,
+                },
+                fragment: Some(wgpu::FragmentState {
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: None,
+                        write_mask: Default::default(),
+                    })],
+                    compilation_options: Default::default(),
+                    entry_point: Some("fg_main"),
+                    module: &shader_module,
+                }),
+                multiview_mask: None,
+                cache: None,
+            })
+        };
+
+        // the crosshair is a full screen quad too, drawn in the main pass
+        // on top of whatever is there
+        let background_pipeline = |fragment_entry: &str, depth_stencil| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("background render pipeline"),
+                depth_stencil,
+                layout: Some(
+                    &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                        label: Some("pipeline layout descriptor"),
+                        bind_group_layouts: &[Some(background_bind_group_layout)],
+                        ..Default::default()
+                    }),
+                ),
+                // drawn into the multisampled target the main pass continues on
+                multisample: wgpu::MultisampleState {
+                    count: 4,
+                    mask: 0xffffffff,
+                    alpha_to_coverage_enabled: false,
+                },
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Cw,
+                    cull_mode: Some(wgpu::Face::Back),
+                    unclipped_depth: false,
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    conservative: false,
+                },
+                vertex: wgpu::VertexState {
+                    buffers: &[wgpu::VertexBufferLayout {
+                        step_mode: Default::default(),
+                        attributes: &wgpu::vertex_attr_array![0 => Float32x3],
+                        array_stride: std::mem::size_of::<[f32; 3]>() as u64,
+                    }],
+                    compilation_options: Default::default(),
+                    entry_point: Some("vx_main"),
+                    module: &background_shader_module,
+                },
+                fragment: Some(wgpu::FragmentState {
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: Default::default(),
+                    })],
+                    compilation_options: Default::default(),
+                    entry_point: Some(fragment_entry),
+                    module: &background_shader_module,
+                }),
+                multiview_mask: None,
+                cache: None,
+            })
+        };
+
+        let shadow_pipeline = |vertex_entry: &str| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("shadow render pipeline"),
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: wgpu::TextureFormat::Depth32Float,
+                    depth_compare: Some(wgpu::CompareFunction::Less),
+                    depth_write_enabled: Some(true),
+                    bias: Default::default(),
+                    stencil: Default::default(),
+                }),
+                layout: Some(
+                    &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                        label: Some("shadow pipeline layout descriptor"),
+                        bind_group_layouts: &[Some(bind_group_layout)],
+                        ..Default::default()
+                    }),
+                ),
+                multisample: Default::default(),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Cw,
+                    cull_mode: None,
+                    unclipped_depth: false,
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    conservative: false,
+                },
+                vertex: wgpu::VertexState {
+                    buffers: &[wgpu::VertexBufferLayout {
+                        step_mode: Default::default(),
+                        attributes: &Vertex::attributes(),
+                        array_stride: Vertex::stride(),
+                    }],
+                    compilation_options: Default::default(),
+                    entry_point: Some(vertex_entry),
+                    module: &shader_module,
+                },
+                fragment: Some(wgpu::FragmentState {
+                    targets: &[],
+                    compilation_options: Default::default(),
+                    entry_point: Some("fg_shadow"),
+                    module: &shader_module,
+                }),
+                multiview_mask: None,
+                cache: None,
+            })
+        };
+
+        let ray_march_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("ray march render pipeline"),
+            // the fragment shader writes the depth of the voxel it hit
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_compare: Some(wgpu::CompareFunction::Always),
+                depth_write_enabled: Some(true),
+                bias: Default::default(),
+                stencil: Default::default(),
+            }),
+            layout: Some(
+                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("ray march pipeline layout descriptor"),
+                    bind_group_layouts: &[
+                        Some(bind_group_layout),
+                        Some(shadow_bind_group_layout),
+                        Some(texture_bind_group_layout),
+                    ],
+                    ..Default::default()
+                }),
+            ),
+            multisample: wgpu::MultisampleState {
+                count: 4,
+                mask: 0xffffffff,
+                alpha_to_coverage_enabled: false,
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Cw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                conservative: false,
+            },
+            vertex: wgpu::VertexState {
+                buffers: &[wgpu::VertexBufferLayout {
+                    step_mode: Default::default(),
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x3],
+                    array_stride: std::mem::size_of::<[f32; 3]>() as u64,
+                }],
+                compilation_options: Default::default(),
+                entry_point: Some("vx_ray_march"),
+                module: &shader_module,
+            },
+            fragment: Some(wgpu::FragmentState {
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: Default::default(),
+                })],
+                compilation_options: Default::default(),
+                entry_point: Some("fg_ray_march"),
+                module: &shader_module,
+            }),
+            multiview_mask: None,
+            cache: None,
+        });
+
+        let pipelines = Pipelines {
+            main: main_pipeline("vx_main"),
+            background: background_pipeline("fg_main", None),
+            crosshair: background_pipeline(
+                "fg_crosshair",
+                Some(wgpu::DepthStencilState {
+                    format: wgpu::TextureFormat::Depth32Float,
+                    depth_compare: Some(wgpu::CompareFunction::Always),
+                    depth_write_enabled: Some(false),
+                    bias: Default::default(),
+                    stencil: Default::default(),
+                }),
+            ),
+            shadow: shadow_pipeline("vx_shadow"),
+            mesh: main_pipeline("vx_mesh"),
+            mesh_shadow: shadow_pipeline("vx_mesh_shadow"),
+            ray_march: ray_march_pipeline,
+        };
+
+        // the description holds the compiler's diagnostics
+        match pollster::block_on(scope.pop()) {
+            Some(wgpu::Error::Validation { description, .. }) => Err(description),
+            Some(error) => Err(error.to_string()),
+            None => Ok(pipelines),
+        }
+    }
+
+    // One byte per voxel, eight times the occupancy texture. Both count
+    // against `WindowSize::MAX_TEXTURE_BYTES`.
+    fn create_chunk_texture(device: &wgpu::Device, size: WindowSize) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("chunk texture"),
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            size: wgpu::Extent3d {
+                width: size.blocks_xz(),
+                height: size.blocks_xz(),
+                depth_or_array_layers: size.blocks_y(),
+            },
+            mip_level_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: wgpu::TextureFormat::R8Uint,
+            sample_count: 1,
+            view_formats: &[],
+        })
+    }
+
+    // One bit per voxel, packed into a word per chunk column like
+    // `Chunk::column`. Laid out as x, z, chunk y like the chunk texture.
+    fn create_occupancy_texture(device: &wgpu::Device, size: WindowSize) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("occupancy texture"),
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            size: wgpu::Extent3d {
+                width: size.blocks_xz(),
+                height: size.blocks_xz(),
+                depth_or_array_layers: size.y,
+            },
+            mip_level_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: wgpu::TextureFormat::R32Uint,
+            sample_count: 1,
+            view_formats: &[],
+        })
+    }
+
+    // One byte per brick, non-zero unless it is all air. Laid out as x, z,
+    // y like the chunk texture.
+    fn create_brick_texture(device: &wgpu::Device, size: WindowSize) -> wgpu::Texture {
+        let [width, height, depth] = Brickmap::new(size).texture_size();
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("brick texture"),
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: depth,
+            },
+            mip_level_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: wgpu::TextureFormat::R8Uint,
+            sample_count: 1,
+            view_formats: &[],
+        })
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        globals_buf: &wgpu::Buffer,
+        chunk_texture: &wgpu::Texture,
+        occupancy_texture: &wgpu::Texture,
+        brick_texture: &wgpu::Texture,
+        block_colors_buf: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("view matrix uniform"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: globals_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(
+                        &chunk_texture.create_view(&Default::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: block_colors_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(
+                        &occupancy_texture.create_view(&Default::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(
+                        &brick_texture.create_view(&Default::default()),
+                    ),
+                },
+            ],
+        })
+    }
+
+    fn create_lattice_buffers(
+        device: &wgpu::Device,
+        size: WindowSize,
+    ) -> (wgpu::Buffer, wgpu::Buffer, wgpu::IndexFormat, u32) {
+        let lattice = lattice::Lattice::new(size.blocks_xz(), size.blocks_y());
+        let (indices, index_format) = lattice.index_data();
+
+        let vertex_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("vertex buffer"),
+            usage: wgpu::BufferUsages::VERTEX,
+            contents: bytemuck::cast_slice(&lattice.vertices),
+        });
+
+        let index_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("index buffer"),
+            usage: wgpu::BufferUsages::INDEX,
+            contents: &indices,
+        });
+
+        (
+            vertex_buf,
+            index_buf,
+            index_format,
+            lattice.indices.len() as u32,
+        )
+    }
+
+    fn create_texture_bind_group(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        textures: &BlockTextures,
+    ) -> wgpu::BindGroup {
+        let size = textures.size();
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("block texture array"),
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            // some backends treat single layer textures as plain 2D textures,
+            // an unused extra layer keeps it an array everywhere
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: textures.layer_count().max(2),
+            },
+            mip_level_count: textures.mip_level_count(),
+            sample_count: 1,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        for layer in 0..textures.layer_count() {
+            for (level, mip) in textures.mips(layer).iter().enumerate() {
+                queue.write_texture(
+                    wgpu::TexelCopyTextureInfo {
+                        texture: &texture,
+                        mip_level: level as u32,
+                        origin: wgpu::Origin3d {
+                            x: 0,
+                            y: 0,
+                            z: layer,
+                        },
+                        aspect: wgpu::TextureAspect::All,
+                    },
+                    mip.as_raw(),
+                    wgpu::TexelCopyBufferLayout {
+                        offset: 0,
+                        bytes_per_row: Some(mip.width() * 4),
+                        rows_per_image: Some(mip.height()),
+                    },
+                    wgpu::Extent3d {
+                        width: mip.width(),
+                        height: mip.height(),
+                        depth_or_array_layers: 1,
+                    },
+                );
+            }
+        }
+
+        let faces_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("block faces buffer"),
+            usage: wgpu::BufferUsages::UNIFORM,
+            contents: bytemuck::bytes_of(&textures.faces_uniform()),
+        });
+
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("block texture bind group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.create_view(
+                        &wgpu::TextureViewDescriptor {
+                            dimension: Some(wgpu::TextureViewDimension::D2Array),
+                            ..Default::default()
+                        },
+                    )),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: faces_buf.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    fn create_depth_texture(device: &wgpu::Device, width: u32, height: u32) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("depth texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            format: wgpu::TextureFormat::Depth32Float,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            dimension: wgpu::TextureDimension::D2,
+            sample_count: 4,
+            mip_level_count: 1,
+            view_formats: &[],
+        })
+    }
+
+    fn create_multisample_texture(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Multisample texture"),
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            size: wgpu::Extent3d {
+                width,
+                height,
+                ..Default::default()
+            },
+            mip_level_count: 1,
+            sample_count: 4,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        })
+    }
+}
@@ -0,0 +1,386 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use crate::block::BlockRegistry;
+use crate::chunk::{Chunk, CHUNK_SIZE};
+use crate::chunk_storage::ChunkPos;
+
+pub const FORMAT_VERSION: u32 = 1;
+// Regions are cubes of REGION_SIZE chunks per axis
+pub const REGION_SIZE: i32 = 8;
+const REGION_CHUNKS: usize = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as usize;
+
+const WORLD_MAGIC: &[u8; 4] = b"VXWD";
+const REGION_MAGIC: &[u8; 4] = b"VXRG";
+const WORLD_FILE: &str = "world.dat";
+
+// magic, version, chunk size, region size
+const HEADER_LEN: u64 = 16;
+// offset and length of every chunk in the region
+const TABLE_LEN: u64 = REGION_CHUNKS as u64 * 8;
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn write_header(w: &mut impl Write, magic: &[u8; 4]) -> io::Result<()> {
+    w.write_all(magic)?;
+    w.write_all(&FORMAT_VERSION.to_le_bytes())?;
+    w.write_all(&(CHUNK_SIZE as u32).to_le_bytes())?;
+    w.write_all(&(REGION_SIZE as u32).to_le_bytes())
+}
+
+fn read_header(r: &mut impl Read, magic: &[u8; 4], path: &Path) -> io::Result<()> {
+    let mut found = [0; 4];
+    r.read_exact(&mut found)?;
+    if &found != magic {
+        return Err(invalid(format!("{}: not a world file", path.display())));
+    }
+    let version = read_u32(r)?;
+    if version != FORMAT_VERSION {
+        return Err(invalid(format!(
+            "{}: unsupported format version {version}",
+            path.display()
+        )));
+    }
+    let chunk_size = read_u32(r)?;
+    let region_size = read_u32(r)?;
+    if chunk_size != CHUNK_SIZE as u32 || region_size != REGION_SIZE as u32 {
+        return Err(invalid(format!(
+            "{}: chunk size {chunk_size} and region size {region_size} do not match {CHUNK_SIZE} and {REGION_SIZE}",
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+pub fn region_of(pos: ChunkPos) -> (ChunkPos, usize) {
+    let region = pos.map(|v| v.div_euclid(REGION_SIZE));
+    let local = pos.map(|v| v.rem_euclid(REGION_SIZE) as usize);
+    let size = REGION_SIZE as usize;
+    (region, (local.x * size + local.z) * size + local.y)
+}
+
+// A world on disk: `world.dat` with the block types and one region file per
+// REGION_SIZE³ chunks, each chunk compressed on its own so a single chunk
+// can be read without inflating the whole region.
+#[derive(Debug, Clone)]
+pub struct WorldSave {
+    dir: PathBuf,
+}
+
+impl WorldSave {
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let save = Self { dir };
+        let world = save.dir.join(WORLD_FILE);
+        if world.exists() {
+            read_header(
+                &mut io::BufReader::new(fs::File::open(&world)?),
+                WORLD_MAGIC,
+                &world,
+            )?;
+        }
+        Ok(save)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn region_path(&self, region: ChunkPos) -> PathBuf {
+        self.dir
+            .join(format!("r.{}.{}.{}.vxr", region.x, region.y, region.z))
+    }
+
+    pub fn load_block_types(&self) -> io::Result<Option<BlockRegistry>> {
+        let path = self.dir.join(WORLD_FILE);
+        let file = match fs::File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut r = io::BufReader::new(file);
+        read_header(&mut r, WORLD_MAGIC, &path)?;
+
+        let mut registry = BlockRegistry::empty();
+        for _ in 0..read_u32(&mut r)? {
+            let mut name = vec![0; read_u32(&mut r)? as usize];
+            r.read_exact(&mut name)?;
+            let name = String::from_utf8(name)
+                .map_err(|_| invalid(format!("{}: invalid block name", path.display())))?;
+            let mut color = [0.0; 4];
+            for c in color.iter_mut() {
+                *c = f32::from_bits(read_u32(&mut r)?);
+            }
+            if registry.register(&name, color).is_none() {
+                return Err(invalid(format!("{}: too many block types", path.display())));
+            }
+        }
+        Ok(Some(registry))
+    }
+
+    pub fn save_block_types(&self, registry: &BlockRegistry) -> io::Result<()> {
+        let mut buf = Vec::new();
+        write_header(&mut buf, WORLD_MAGIC)?;
+        buf.write_all(&(registry.len() as u32).to_le_bytes())?;
+        for t in registry.iter() {
+            buf.write_all(&(t.name.len() as u32).to_le_bytes())?;
+            buf.write_all(t.name.as_bytes())?;
+            for c in t.color {
+                buf.write_all(&c.to_bits().to_le_bytes())?;
+            }
+        }
+        self.replace_file(&self.dir.join(WORLD_FILE), &buf)
+    }
+
+    pub fn load_chunk(&self, pos: ChunkPos) -> io::Result<Option<Chunk>> {
+        let (region, index) = region_of(pos);
+        let path = self.region_path(region);
+        let mut file = match fs::File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        read_header(&mut file, REGION_MAGIC, &path)?;
+
+        file.seek(SeekFrom::Start(HEADER_LEN + index as u64 * 8))?;
+        let offset = read_u32(&mut file)? as u64;
+        let len = read_u32(&mut file)? as u64;
+        if len == 0 {
+            return Ok(None);
+        }
+        file.seek(SeekFrom::Start(offset))?;
+        let chunk = Chunk::read_from(&mut ZlibDecoder::new(file.take(len)))
+            .map_err(|e| invalid(format!("{}: chunk {index}: {e}", path.display())))?;
+        Ok(Some(chunk))
+    }
+
+    // Writes the chunks into their region files, keeping every other chunk
+    // already stored in those regions. `None` clears a chunk from the save.
+    pub fn save_chunks<'a>(
+        &self,
+        chunks: impl IntoIterator<Item = (ChunkPos, Option<&'a Chunk>)>,
+    ) -> io::Result<()> {
+        let mut regions: HashMap<ChunkPos, Vec<(usize, Option<&Chunk>)>> = HashMap::new();
+        for (pos, chunk) in chunks {
+            let (region, index) = region_of(pos);
+            regions.entry(region).or_default().push((index, chunk));
+        }
+
+        for (region, chunks) in regions {
+            let path = self.region_path(region);
+            let mut blobs = self.read_region(&path)?;
+            for (index, chunk) in chunks {
+                blobs[index] = match chunk {
+                    Some(chunk) => {
+                        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                        chunk.write_to(&mut encoder)?;
+                        Some(encoder.finish()?)
+                    }
+                    None => None,
+                };
+            }
+
+            let mut table = Vec::with_capacity(TABLE_LEN as usize);
+            let mut data = Vec::new();
+            for blob in &blobs {
+                let (offset, len) = match blob {
+                    Some(blob) => (HEADER_LEN + TABLE_LEN + data.len() as u64, blob.len()),
+                    None => (0, 0),
+                };
+                table.extend_from_slice(&(offset as u32).to_le_bytes());
+                table.extend_from_slice(&(len as u32).to_le_bytes());
+                if let Some(blob) = blob {
+                    data.extend_from_slice(blob);
+                }
+            }
+
+            let mut buf = Vec::with_capacity((HEADER_LEN + TABLE_LEN) as usize + data.len());
+            write_header(&mut buf, REGION_MAGIC)?;
+            buf.extend_from_slice(&table);
+            buf.extend_from_slice(&data);
+            self.replace_file(&path, &buf)?;
+        }
+        Ok(())
+    }
+
+    fn read_region(&self, path: &Path) -> io::Result<Vec<Option<Vec<u8>>>> {
+        let mut blobs = vec![None; REGION_CHUNKS];
+        let buf = match fs::read(path) {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(blobs),
+            Err(e) => return Err(e),
+        };
+        read_header(&mut buf.as_slice(), REGION_MAGIC, path)?;
+
+        let table = buf
+            .get(HEADER_LEN as usize..(HEADER_LEN + TABLE_LEN) as usize)
+            .ok_or_else(|| invalid(format!("{}: truncated region table", path.display())))?;
+        for (blob, entry) in blobs.iter_mut().zip(table.chunks_exact(8)) {
+            let offset = u32::from_le_bytes(entry[0..4].try_into().unwrap()) as usize;
+            let len = u32::from_le_bytes(entry[4..8].try_into().unwrap()) as usize;
+            if len == 0 {
+                continue;
+            }
+            let data = buf
+                .get(offset..offset + len)
+                .ok_or_else(|| invalid(format!("{}: truncated chunk data", path.display())))?;
+            *blob = Some(data.to_vec());
+        }
+        Ok(blobs)
+    }
+
+    // Readers on the chunk workers must never see a half written file.
+    fn replace_file(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, path)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::block;
+
+    // Fresh directory under the system temp dir, removed again on drop
+    pub(crate) struct TempDir(pub PathBuf);
+
+    impl TempDir {
+        pub(crate) fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("voxel_render_{name}_{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn test_chunk(seed: usize) -> Chunk {
+        let mut chunk = Chunk::empty();
+        for i in 0..200 {
+            let v = i * 7 + seed;
+            let block = [block::STONE, block::DIRT, block::GRASS][v % 3];
+            chunk.set(
+                v % CHUNK_SIZE,
+                (v / 3) % CHUNK_SIZE,
+                (v / 5) % CHUNK_SIZE,
+                block,
+            );
+        }
+        chunk
+    }
+
+    #[test]
+    fn chunks_and_block_types_round_trip() {
+        let dir = TempDir::new("round_trip");
+        let positions = [
+            ChunkPos::new(0, 0, 0),
+            ChunkPos::new(1, 0, 0),
+            ChunkPos::new(-1, -3, 9),
+            ChunkPos::new(20, 2, -17),
+        ];
+        let chunks: Vec<Chunk> = (0..positions.len()).map(test_chunk).collect();
+        let mut block_types = block::BlockRegistry::new();
+        block_types.register("glass", [0.6, 0.8, 0.9, 0.5]);
+
+        {
+            let save = WorldSave::open(&dir.0).unwrap();
+            save.save_block_types(&block_types).unwrap();
+            save.save_chunks(positions.iter().copied().zip(chunks.iter().map(Some)))
+                .unwrap();
+            // a second save into the same region keeps the first
+            save.save_chunks([(ChunkPos::new(0, 1, 0), Some(&Chunk::filled(block::SAND)))])
+                .unwrap();
+        }
+
+        let save = WorldSave::open(&dir.0).unwrap();
+        for (pos, chunk) in positions.iter().zip(&chunks) {
+            assert_eq!(save.load_chunk(*pos).unwrap().as_ref(), Some(chunk));
+        }
+        assert_eq!(
+            save.load_chunk(ChunkPos::new(0, 1, 0)).unwrap(),
+            Some(Chunk::filled(block::SAND))
+        );
+        assert_eq!(save.load_chunk(ChunkPos::new(2, 0, 0)).unwrap(), None);
+
+        let loaded = save.load_block_types().unwrap().unwrap();
+        assert_eq!(loaded.len(), block_types.len());
+        for (a, b) in loaded.iter().zip(block_types.iter()) {
+            assert_eq!(a.name, b.name);
+            assert_eq!(a.color, b.color);
+        }
+    }
+
+    #[test]
+    fn cleared_chunks_are_gone() {
+        let dir = TempDir::new("cleared");
+        let save = WorldSave::open(&dir.0).unwrap();
+        let (a, b) = (ChunkPos::new(0, 0, 0), ChunkPos::new(0, 0, 1));
+        save.save_chunks([(a, Some(&test_chunk(0))), (b, Some(&test_chunk(1)))])
+            .unwrap();
+        save.save_chunks([(a, None)]).unwrap();
+        assert_eq!(save.load_chunk(a).unwrap(), None);
+        assert_eq!(save.load_chunk(b).unwrap(), Some(test_chunk(1)));
+    }
+
+    #[test]
+    fn bad_headers_are_rejected() {
+        let dir = TempDir::new("bad_headers");
+        fs::create_dir_all(&dir.0).unwrap();
+        let header = |magic: &[u8; 4], version: u32, chunk_size: u32| {
+            let mut buf = magic.to_vec();
+            for v in [version, chunk_size, REGION_SIZE as u32] {
+                buf.extend_from_slice(&v.to_le_bytes());
+            }
+            buf
+        };
+
+        let world = dir.0.join(WORLD_FILE);
+        fs::write(
+            &world,
+            header(WORLD_MAGIC, FORMAT_VERSION, CHUNK_SIZE as u32),
+        )
+        .unwrap();
+        assert!(WorldSave::open(&dir.0).is_ok());
+        for bad in [
+            header(b"NOPE", FORMAT_VERSION, CHUNK_SIZE as u32),
+            header(WORLD_MAGIC, FORMAT_VERSION + 1, CHUNK_SIZE as u32),
+            header(WORLD_MAGIC, FORMAT_VERSION, CHUNK_SIZE as u32 * 2),
+            WORLD_MAGIC.to_vec(),
+        ] {
+            fs::write(&world, bad).unwrap();
+            let e = WorldSave::open(&dir.0).unwrap_err();
+            assert!(matches!(
+                e.kind(),
+                io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+            ));
+        }
+
+        // region files are checked when a chunk is read from them
+        fs::remove_file(&world).unwrap();
+        let save = WorldSave::open(&dir.0).unwrap();
+        let (region, _) = region_of(ChunkPos::new(0, 0, 0));
+        let mut bad = header(REGION_MAGIC, FORMAT_VERSION, CHUNK_SIZE as u32 + 1);
+        bad.resize((HEADER_LEN + TABLE_LEN) as usize, 0);
+        fs::write(save.region_path(region), bad).unwrap();
+        assert!(save.load_chunk(ChunkPos::new(0, 0, 0)).is_err());
+    }
+}
@@ -1,9 +1,3 @@
-const XZ: u32 = 128;
-const Y: u32 = 64;
-
-const NUM_XZ_VERTICES: u32 = XZ * 4;
-const NUM_Y_VERTICES: u32 = Y * 4;
-
 const AXIS_X: u32 = 0;
 const AXIS_Y: u32 = 1;
 const AXIS_Z: u32 = 2;
@@ -11,20 +5,42 @@ const AXIS_Z: u32 = 2;
 const FACE_FRONT: bool = false;
 const FACE_BACK: bool = true;
 
-const CHUNK_SIZE: u32 = 32;
-const CHUNK_STRIDE: u32 = XZ / CHUNK_SIZE;
-const CHUNK_COUNT: u32 = CHUNK_STRIDE * CHUNK_STRIDE;
-const CHUNK_ARRAY_SIZE: u32 = CHUNK_COUNT * CHUNK_SIZE * CHUNK_SIZE / 4;
+const CASCADE_COUNT: u32 = 4;
+
+// edge length of the cubes of voxels the brick map keeps a flag for
+const BRICK_SIZE: i32 = 8;
 
-const epsilon: f32 = 0.001;
+// width of the band along the window sides that fades into the fog
+const EDGE_FADE: f32 = 12.0;
 
 struct GlobalsUniform {
     proj_view_mat: mat4x4<f32>,
-    light_mat: mat4x4<f32>,
+    inv_proj_view_mat: mat4x4<f32>,
+    light_mats: array<mat4x4<f32>, CASCADE_COUNT>,
     cam_pos: vec3<f32>,
     cam_dir: vec3<f32>,
     light_dir: vec3<f32>,
     grid_lines: u32,
+    window_offset: vec3<i32>,
+    has_target: u32,
+    target_voxel: vec3<i32>,
+    _pad2: u32,
+    target_normal: vec3<i32>,
+    // trace shadow rays through the voxels instead of using the shadow map
+    traced_shadows: u32,
+    resolution: vec2<u32>,
+    ao_enabled: u32,
+    ao_strength: f32,
+    shadow_bias: f32,
+    shadow_normal_bias: f32,
+    shadow_pcf_radius: i32,
+    // far end of every cascade along the view direction
+    cascade_splits: vec4<f32>,
+    light_color: vec3<f32>,
+    ambient_color: vec3<f32>,
+    fog: Fog,
+    // texel of the chunk texture holding the window's first voxel
+    window_wrap: vec3<u32>,
 }
 
 @group(0) @binding(0)
@@ -33,8 +49,41 @@ var<uniform> globals: GlobalsUniform;
 @group(0) @binding(1)
 var blocks: texture_3d<u32>;
 
+struct BlockColors {
+    colors: array<vec4<f32>, 256>,
+}
+
+@group(0) @binding(2)
+var<uniform> block_colors: BlockColors;
+
+// bit y of every texel is set if the voxel at that height in the chunk
+// column is not air
+@group(0) @binding(3)
+var occupancy: texture_3d<u32>;
+
+// non-zero for every brick of the window that is not all air
+@group(0) @binding(4)
+var brick_map: texture_3d<u32>;
+
 @group(1) @binding(0)
-var shadow_map: texture_2d<f32>;
+var shadow_map: texture_depth_2d_array;
+
+@group(1) @binding(1)
+var shadow_sampler: sampler_comparison;
+
+@group(2) @binding(0)
+var block_textures: texture_2d_array<f32>;
+
+@group(2) @binding(1)
+var block_sampler: sampler;
+
+// texture layer of the top, side and bottom faces of every block
+struct BlockFaces {
+    layers: array<vec4<u32>, 256>,
+}
+
+@group(2) @binding(2)
+var<uniform> block_faces: BlockFaces;
 
 struct VertexInput {
     @location(0) pos: vec3<f32>,
@@ -44,33 +93,50 @@ struct VertexInput {
 struct VertexOutput {
     @builtin(position) pos: vec4<f32>,
     @location(0) world_pos: vec3<f32>,
-    @location(1) light_pos: vec3<f32>,
-    @location(2) axis: u32,
+    @location(1) axis: u32,
 }
 
-@vertex
-fn vx_main(in: VertexInput) -> VertexOutput {
+fn main_vertex(world_pos: vec3<f32>, axis: u32) -> VertexOutput {
     var out: VertexOutput;
-    out.pos = globals.proj_view_mat * vec4<f32>(in.pos, 1.0);
-    out.world_pos = in.pos;
-
-    let light_pos = globals.light_mat * vec4<f32>(in.pos, 1.0);
-    out.light_pos = light_pos.xyz / light_pos.w;
-    out.axis = in.axis;
+    out.pos = globals.proj_view_mat * vec4<f32>(world_pos, 1.0);
+    out.world_pos = world_pos;
+    out.axis = axis;
     return out;
 }
 
-@vertex
-fn vx_shadow(in: VertexInput) -> VertexOutput {
+fn shadow_vertex(world_pos: vec3<f32>, axis: u32, cascade: u32) -> VertexOutput {
     var out: VertexOutput;
-    let light_pos = globals.light_mat * vec4<f32>(in.pos, 1.0);
+    let light_pos = globals.light_mats[cascade] * vec4<f32>(world_pos, 1.0);
     out.pos = light_pos / light_pos.w;
-    out.world_pos = in.pos;
-    out.axis = in.axis;
+    out.world_pos = world_pos;
+    out.axis = axis;
     return out;
 }
 
-fn get_block(coord: vec3<f32>, axis: u32, face: bool) -> bool {
+// Lattice planes are relative to the window
+@vertex
+fn vx_main(in: VertexInput) -> VertexOutput {
+    return main_vertex(in.pos + vec3<f32>(globals.window_offset), in.axis);
+}
+
+@vertex
+fn vx_shadow(in: VertexInput, @builtin(instance_index) cascade: u32) -> VertexOutput {
+    return shadow_vertex(in.pos + vec3<f32>(globals.window_offset), in.axis, cascade);
+}
+
+// Chunk meshes are in world coordinates already
+@vertex
+fn vx_mesh(in: VertexInput) -> VertexOutput {
+    return main_vertex(in.pos, in.axis);
+}
+
+@vertex
+fn vx_mesh_shadow(in: VertexInput, @builtin(instance_index) cascade: u32) -> VertexOutput {
+    return shadow_vertex(in.pos, in.axis, cascade);
+}
+
+// World position of the voxel behind the face at `coord`
+fn block_coord(coord: vec3<f32>, axis: u32, face: bool) -> vec3<i32> {
     let offset = select(0.0, -1.0, face == FACE_BACK);
     var coord_i: vec3<i32>;
 
@@ -87,38 +153,70 @@ fn get_block(coord: vec3<f32>, axis: u32, face: bool) -> bool {
         coord_i.y = i32(floor(coord.y));
         coord_i.z = i32(round(coord.z + offset));
     }
+    return coord_i;
+}
+
+fn get_block_id(coord: vec3<f32>, axis: u32, face: bool) -> u32 {
+    return voxel_id(block_coord(coord, axis, face));
+}
+
+// Window size in voxels, the chunk texture is stored as x, z, y
+fn window_dims() -> vec3<i32> {
+    return vec3<i32>(textureDimensions(blocks).xzy);
+}
+
+// Voxel of the chunk texture holding `coord_i`, negative outside the window
+fn window_texel(coord_i: vec3<i32>) -> vec3<i32> {
+    let dims = window_dims();
+    let local = coord_i - globals.window_offset;
+    if any(local < vec3<i32>(0)) || any(local >= dims) {
+        return vec3<i32>(-1);
+    }
+
+    // chunks wrap around inside the texture as the window moves
+    return (local + vec3<i32>(globals.window_wrap)) % dims;
+}
+
+fn voxel_id(coord_i: vec3<i32>) -> u32 {
+    let texel = window_texel(coord_i);
+    if texel.x < 0 {
+        return 0u;
+    }
+    let tx = textureLoad(blocks, texel.xzy, 0);
+    return tx[0];
+}
 
-    if coord_i.x < -i32(XZ / 2) || coord_i.x >= i32(XZ)
-        || coord_i.z < -i32(XZ / 2) || coord_i.z >= i32(XZ)
-        || coord_i.y < -i32(XZ / 2) || coord_i.y >= i32(Y)
-    {
+// Whether a voxel is not air, from the occupancy bits which take an eighth
+// of the memory of the block ids
+fn is_solid(coord_i: vec3<i32>) -> bool {
+    let texel = window_texel(coord_i);
+    if texel.x < 0 {
         return false;
     }
+    // chunk slots are aligned, so y / 32 is the slot and y % 32 the bit
+    let column = textureLoad(occupancy, vec3<i32>(texel.x, texel.z, texel.y / 32), 0)[0];
+    return ((column >> u32(texel.y % 32)) & 1u) != 0u;
+}
 
-    let tx = textureLoad(
-        blocks,
-        vec3<i32>(
-            coord_i.x + i32(XZ / 2),
-            coord_i.z + i32(XZ / 2),
-            coord_i.y + i32(Y / 2),
-        ),
-        0,
-    );
-    return bool(tx[0]);
+fn brick_empty(coord_i: vec3<i32>) -> bool {
+    let texel = window_texel(coord_i);
+    if texel.x < 0 {
+        return true;
+    }
+    return textureLoad(brick_map, (texel / BRICK_SIZE).xzy, 0)[0] == 0u;
+}
+
+fn get_block(coord: vec3<f32>, axis: u32, face: bool) -> bool {
+    return is_solid(block_coord(coord, axis, face));
 }
 
 struct FragmentInput {
+    @builtin(position) frag_pos: vec4<f32>,
     @location(0) world_pos: vec3<f32>,
-    @location(1) light_pos: vec3<f32>,
-    @location(2) axis: u32,
+    @location(1) axis: u32,
 }
 
-fn block_color(coord: vec3<f32>, axis: u32, face: bool) -> vec4<f32> {
-    let block = get_block(coord, axis, face);
-    if !block {
-        return vec4<f32>(0.0);
-    }
-
+fn face_normal(axis: u32, face: bool) -> vec3<f32> {
     var normal: vec3<f32>;
     if axis == AXIS_X {
         normal = vec3<f32>(1.0, 0.0, 0.0);
@@ -130,14 +228,105 @@ fn block_color(coord: vec3<f32>, axis: u32, face: bool) -> vec4<f32> {
     if face == FACE_BACK {
         normal *= -1.0;
     }
+    return normal;
+}
 
+// Texture coordinates on a face. They are not wrapped per voxel so the
+// derivatives stay smooth, the sampler repeats them instead.
+fn face_uv(coord: vec3<f32>, axis: u32) -> vec2<f32> {
+    if axis == AXIS_X {
+        return vec2<f32>(coord.z, -coord.y);
+    } else if axis == AXIS_Y {
+        return coord.xz;
+    }
+    return vec2<f32>(coord.x, -coord.y);
+}
+
+fn vertex_ao(side1: bool, side2: bool, corner: bool) -> f32 {
+    if side1 && side2 {
+        return 0.0;
+    }
+    return (3.0 - f32(side1) - f32(side2) - f32(corner)) / 3.0;
+}
+
+// Occlusion from the cells around the empty cell in front of the face,
+// computed per face corner and interpolated across the face.
+fn ambient_occlusion(coord: vec3<f32>, axis: u32, face: bool) -> f32 {
+    let front = block_coord(coord, axis, face) - vec3<i32>(face_normal(axis, face));
+    var u: vec3<i32>;
+    var v: vec3<i32>;
+    var uv: vec2<f32>;
+    if axis == AXIS_X {
+        u = vec3<i32>(0, 1, 0);
+        v = vec3<i32>(0, 0, 1);
+        uv = coord.yz;
+    } else if axis == AXIS_Y {
+        u = vec3<i32>(1, 0, 0);
+        v = vec3<i32>(0, 0, 1);
+        uv = coord.xz;
+    } else {
+        u = vec3<i32>(1, 0, 0);
+        v = vec3<i32>(0, 1, 0);
+        uv = coord.xy;
+    }
+
+    let u0 = is_solid(front - u);
+    let u1 = is_solid(front + u);
+    let v0 = is_solid(front - v);
+    let v1 = is_solid(front + v);
+    let ao00 = vertex_ao(u0, v0, is_solid(front - u - v));
+    let ao10 = vertex_ao(u1, v0, is_solid(front + u - v));
+    let ao01 = vertex_ao(u0, v1, is_solid(front - u + v));
+    let ao11 = vertex_ao(u1, v1, is_solid(front + u + v));
+
+    let f = fract(uv);
+    return mix(mix(ao00, ao10, f.x), mix(ao01, ao11, f.x), f.y);
+}
+
+fn block_color(
+    coord: vec3<f32>,
+    axis: u32,
+    face: bool,
+    uv: vec2<f32>,
+    uv_dx: vec2<f32>,
+    uv_dy: vec2<f32>,
+) -> vec4<f32> {
+    let block = get_block_id(coord, axis, face);
+    if block == 0u {
+        return vec4<f32>(0.0);
+    }
+
+    let normal = face_normal(axis, face);
     let exposed = !get_block(coord - normal, axis, face);
     if !exposed {
         return vec4<f32>(0.0);;
     }
 
-    let dot = dot(globals.light_dir, normal) + 0.3;
-    return vec4<f32>(vec3<f32>(dot), 1.0);
+    // the normal points into the block, so -y is the top face
+    let faces = block_faces.layers[block];
+    var layer = faces.y;
+    if normal.y < 0.0 {
+        layer = faces.x;
+    } else if normal.y > 0.0 {
+        layer = faces.z;
+    }
+    let texel = textureSampleGrad(block_textures, block_sampler, uv, layer, uv_dx, uv_dy).rgb;
+    // layer 0 is white, untextured blocks keep their flat colour
+    let tint = select(block_colors.colors[block].rgb, vec3<f32>(1.0), layer != 0u);
+
+    var diffuse = max(dot(globals.light_dir, normal), 0.0);
+    if diffuse > 0.0 {
+        if globals.traced_shadows != 0 {
+            diffuse *= traced_shadow(coord, normal);
+        } else {
+            diffuse *= shadow(coord, normal);
+        }
+    }
+    var light = globals.ambient_color + globals.light_color * diffuse;
+    if globals.ao_enabled != 0 {
+        light *= 1.0 - globals.ao_strength * (1.0 - ambient_occlusion(coord, axis, face));
+    }
+    return vec4<f32>(tint * texel * light, 1.0);
 }
 
 fn grid_dist(v: f32, size: f32) -> f32 {
@@ -145,7 +334,8 @@ fn grid_dist(v: f32, size: f32) -> f32 {
     return min(dist, 1.0 - dist) * size;
 }
 
-fn chunk_grid(coord: vec3<f32>, axis: u32, face: bool) -> bool {
+// `w` is the size of a pixel in world units
+fn chunk_grid(coord: vec3<f32>, w: f32) -> bool {
     let thickness = 2.0;
     let grid_size = 32.0;
 
@@ -159,8 +349,6 @@ fn chunk_grid(coord: vec3<f32>, axis: u32, face: bool) -> bool {
     let dist_l3 = length(vec2<f32>(dy4, dx32));
     let dist_l4 = length(vec2<f32>(dy4, dz32));
 
-    let w = fwidth(coord.y);
-
     let l1 = step(dist_l1, w * thickness);
     let l2 = step(dist_l2, w * thickness);
     let l3 = step(dist_l3, w * thickness);
@@ -171,47 +359,135 @@ fn chunk_grid(coord: vec3<f32>, axis: u32, face: bool) -> bool {
     return line == 1.0;
 }
 
+// Fraction of light reaching `coord`, averaged over a square of
+// (2 * radius + 1)² comparison samples which are bilinear themselves.
+// Everything beyond the last cascade is lit.
+fn shadow(coord: vec3<f32>, normal: vec3<f32>) -> f32 {
+    let view_depth = dot(coord - globals.cam_pos, globals.cam_dir);
+    var cascade = 0u;
+    while cascade < CASCADE_COUNT && view_depth > globals.cascade_splits[cascade] {
+        cascade++;
+    }
+    if cascade == CASCADE_COUNT {
+        return 1.0;
+    }
+
+    // the normal points into the block, offset towards the light instead
+    let offset_pos = coord - normal * globals.shadow_normal_bias;
+    let light_pos = globals.light_mats[cascade] * vec4<f32>(offset_pos, 1.0);
+    let pos = light_pos.xyz / light_pos.w;
+    let uv = vec2<f32>(pos.x * 0.5 + 0.5, -pos.y * 0.5 + 0.5);
+    let depth = pos.z - globals.shadow_bias;
+
+    let texel = 1.0 / vec2<f32>(textureDimensions(shadow_map));
+    let radius = globals.shadow_pcf_radius;
+    var lit = 0.0;
+    for (var y = -radius; y <= radius; y++) {
+        for (var x = -radius; x <= radius; x++) {
+            let sample_uv = uv + vec2<f32>(f32(x), f32(y)) * texel;
+            lit += textureSampleCompareLevel(shadow_map, shadow_sampler, sample_uv, cascade, depth);
+        }
+    }
+    let size = f32(2 * radius + 1);
+    return lit / (size * size);
+}
+
+// The two coordinates of `coord` that lie in a face of `axis`
+fn in_plane(coord: vec3<f32>, axis: u32) -> vec2<f32> {
+    if axis == AXIS_X {
+        return coord.yz;
+    } else if axis == AXIS_Y {
+        return coord.xz;
+    }
+    return coord.xy;
+}
+
+// Distance in pixels to the closest edge of the face at `coord`, `width`
+// is the size of a pixel in the face's coordinates
+fn face_edge_dist(coord: vec3<f32>, axis: u32, width: vec2<f32>) -> f32 {
+    let uv = in_plane(coord, axis);
+    let edge = min(fract(uv), 1.0 - fract(uv)) / width;
+    return min(edge.x, edge.y);
+}
+
+// Side of the face the camera looks at
+fn facing(coord: vec3<f32>, axis: u32) -> bool {
+    return select(FACE_FRONT, FACE_BACK,
+        (axis == AXIS_X && coord.x < globals.cam_pos.x)
+        || (axis == AXIS_Y && coord.y < globals.cam_pos.y)
+        || (axis == AXIS_Z && coord.z < globals.cam_pos.z)
+    );
+}
+
 @fragment
 fn fg_main(in: FragmentInput) -> @location(0) vec4<f32> {
-
-    let face = select(FACE_FRONT, FACE_BACK,
-        (in.axis == AXIS_X && in.world_pos.x < globals.cam_pos.x)
-        || (in.axis == AXIS_Y && in.world_pos.y < globals.cam_pos.y)
-        || (in.axis == AXIS_Z && in.world_pos.z < globals.cam_pos.z)
+    let face = facing(in.world_pos, in.axis);
+
+    // derivatives have to be taken before any non-uniform branch
+    let uv = face_uv(in.world_pos, in.axis);
+    let color = shade(
+        in.world_pos,
+        in.axis,
+        face,
+        fwidth(in.world_pos.y),
+        fwidth(in_plane(in.world_pos, in.axis)),
+        uv,
+        dpdx(uv),
+        dpdy(uv),
     );
+    if color.w == 0.0 {
+        discard;
+    }
+    return color;
+}
 
-    if globals.grid_lines != 0 && chunk_grid(in.world_pos, in.axis, face) {
+// Colour of the face at `coord` with the overlays and fog on top, zero
+// alpha where there is no visible face. `pixel_y` and `pixel` are the size
+// of a pixel in world units along y and in the face's coordinates.
+fn shade(
+    coord: vec3<f32>,
+    axis: u32,
+    face: bool,
+    pixel_y: f32,
+    pixel: vec2<f32>,
+    uv: vec2<f32>,
+    uv_dx: vec2<f32>,
+    uv_dy: vec2<f32>,
+) -> vec4<f32> {
+    if globals.grid_lines != 0 && chunk_grid(coord, pixel_y) {
         return vec4<f32>(1.0, 1.0, 0.0, 1.0);
     }
 
-    var block_color = block_color(in.world_pos, in.axis, face);
+    var block_color = block_color(coord, axis, face, uv, uv_dx, uv_dy);
 
     if block_color.w == 0.0 {
-        discard;
+        return block_color;
     }
 
-    let uv = vec2<f32>(
-        in.light_pos.x * 0.5 + 0.5,
-        -in.light_pos.y * 0.5 + 0.5,
-    );
-    let dim = vec2<f32>(textureDimensions(shadow_map));
-    let depth = in.light_pos.z;
-
-    let texel_coords = vec2<u32>(
-        clamp(uv * dim, vec2<f32>(0.0), vec2<f32>(dim - vec2<f32>(1.0)))
-    );
-
-    let shadow_depth = textureLoad(
-        shadow_map,
-        texel_coords,
-        0,
-    )[0];
-    //return vec4<f32>(depth, depth, depth, 1.0);
+    if globals.fog.density > 0.0 {
+        let to_frag = coord - globals.cam_pos;
+        let dist = length(to_frag);
+        let dir = to_frag / dist;
+        // hide where the window sides and the far plane cut the world off
+        let local = coord - vec3<f32>(globals.window_offset);
+        let far_side = vec3<f32>(window_dims()) - local;
+        let edge = min(min(local.x, local.z), min(far_side.x, far_side.z));
+        let cutoff = max(
+            1.0 - smoothstep(0.0, EDGE_FADE, edge),
+            smoothstep(0.8 * globals.fog.far, globals.fog.far, dist),
+        );
+        let fog = max(fog_amount(globals.fog, dir, dist), cutoff);
+        block_color = vec4<f32>(mix(block_color.rgb, fog_color(globals.fog, dir), fog), 1.0);
+    }
 
-    if depth > shadow_depth + epsilon {
-        block_color.x *= 0.2;
-        block_color.y *= 0.2;
-        block_color.z *= 0.2;
+    if globals.has_target != 0
+        && all(block_coord(coord, axis, face) == globals.target_voxel) {
+        let on_face = all(vec3<i32>(face_normal(axis, face)) == globals.target_normal);
+        let tint = select(0.15, 0.35, on_face);
+        block_color = vec4<f32>(mix(block_color.rgb, vec3<f32>(1.0), tint), 1.0);
+        if face_edge_dist(coord, axis, pixel) < 1.5 {
+            block_color = vec4<f32>(0.05, 0.05, 0.05, 1.0);
+        }
     }
 
     return block_color;
@@ -230,3 +506,204 @@ fn fg_shadow(in: FragmentInput) {
     }
 }
 
+
+// First cell a ray enters
+struct Hit {
+    found: bool,
+    t: f32,
+    cell: vec3<i32>,
+    // axis of the face the ray crossed into the cell
+    axis: u32,
+}
+
+// Walks the voxels of the window along `dir` with Amanatides-Woo DDA and
+// returns the first solid voxel entered from an empty one, so a ray that
+// starts inside a block looks out of it like the lattice does. Empty bricks
+// are crossed in one step.
+fn march(origin: vec3<f32>, dir: vec3<f32>) -> Hit {
+    var hit: Hit;
+    hit.found = false;
+
+    let dims = window_dims();
+    let low = vec3<f32>(globals.window_offset);
+    let high = low + vec3<f32>(dims);
+    let safe_dir = select(dir, vec3<f32>(1e-9), abs(dir) < vec3<f32>(1e-9));
+    let inv_dir = 1.0 / safe_dir;
+    let t_low = (low - origin) * inv_dir;
+    let t_high = (high - origin) * inv_dir;
+    let t_near = min(t_low, t_high);
+    let t_far = max(t_low, t_high);
+    let t_enter = max(max(t_near.x, t_near.y), t_near.z);
+    let t_exit = min(min(t_far.x, t_far.y), t_far.z);
+    if t_exit < max(t_enter, 0.0) {
+        return hit;
+    }
+
+    var t = max(t_enter, 0.0);
+    var axis = AXIS_X;
+    if t_enter == t_near.y {
+        axis = AXIS_Y;
+    } else if t_enter == t_near.z {
+        axis = AXIS_Z;
+    }
+    var cell = clamp(
+        vec3<i32>(floor(origin + dir * t)),
+        globals.window_offset,
+        globals.window_offset + dims - 1,
+    );
+    let step = vec3<i32>(sign(safe_dir));
+    let t_delta = abs(inv_dir);
+    let next = vec3<f32>(cell) + select(vec3<f32>(0.0), vec3<f32>(1.0), safe_dir > vec3<f32>(0.0));
+    var t_next = (next - origin) * inv_dir;
+
+    var prev_solid = t_enter <= 0.0 && is_solid(cell);
+    let max_steps = dims.x + dims.y + dims.z + 3;
+    for (var i = 0; i < max_steps && t <= t_exit; i++) {
+        if window_texel(cell).x < 0 {
+            break;
+        }
+        if brick_empty(cell) {
+            // bricks line up with the chunks, which line up with the window
+            let brick = globals.window_offset
+                + (cell - globals.window_offset) / BRICK_SIZE * BRICK_SIZE;
+            let far_side = vec3<f32>(brick)
+                + select(vec3<f32>(0.0), vec3<f32>(f32(BRICK_SIZE)), safe_dir > vec3<f32>(0.0));
+            let t_side = (far_side - origin) * inv_dir;
+            if t_side.x < t_side.y && t_side.x < t_side.z {
+                t = t_side.x;
+                axis = AXIS_X;
+            } else if t_side.y < t_side.z {
+                t = t_side.y;
+                axis = AXIS_Y;
+            } else {
+                t = t_side.z;
+                axis = AXIS_Z;
+            }
+
+            // the cell past the side the ray leaves the brick through
+            cell = clamp(vec3<i32>(floor(origin + dir * t)), brick, brick + BRICK_SIZE - 1);
+            if axis == AXIS_X {
+                cell.x = select(brick.x - 1, brick.x + BRICK_SIZE, step.x > 0);
+            } else if axis == AXIS_Y {
+                cell.y = select(brick.y - 1, brick.y + BRICK_SIZE, step.y > 0);
+            } else {
+                cell.z = select(brick.z - 1, brick.z + BRICK_SIZE, step.z > 0);
+            }
+            let next = vec3<f32>(cell) + select(vec3<f32>(0.0), vec3<f32>(1.0), safe_dir > vec3<f32>(0.0));
+            t_next = (next - origin) * inv_dir;
+            prev_solid = false;
+            continue;
+        }
+
+        let solid = is_solid(cell);
+        if solid && !prev_solid {
+            hit.found = true;
+            hit.t = t;
+            hit.cell = cell;
+            hit.axis = axis;
+            return hit;
+        }
+        prev_solid = solid;
+
+        if t_next.x < t_next.y && t_next.x < t_next.z {
+            cell.x += step.x;
+            t = t_next.x;
+            t_next.x += t_delta.x;
+            axis = AXIS_X;
+        } else if t_next.y < t_next.z {
+            cell.y += step.y;
+            t = t_next.y;
+            t_next.y += t_delta.y;
+            axis = AXIS_Y;
+        } else {
+            cell.z += step.z;
+            t = t_next.z;
+            t_next.z += t_delta.z;
+            axis = AXIS_Z;
+        }
+    }
+    return hit;
+}
+
+// 0 if a voxel between the face at `coord` and the light blocks it
+fn traced_shadow(coord: vec3<f32>, normal: vec3<f32>) -> f32 {
+    // the normal points into the block, start in the empty cell in front
+    let hit = march(coord - normal * 1e-3, -globals.light_dir);
+    return select(1.0, 0.0, hit.found);
+}
+
+// Direction of the camera ray through `ndc`, not normalized
+fn camera_ray(ndc: vec2<f32>) -> vec3<f32> {
+    let near = globals.inv_proj_view_mat * vec4<f32>(ndc, 0.0, 1.0);
+    let far = globals.inv_proj_view_mat * vec4<f32>(ndc, 1.0, 1.0);
+    return far.xyz / far.w - near.xyz / near.w;
+}
+
+// Where the ray along `dir` from the camera meets the plane through
+// `coord` with normal `axis`
+fn ray_plane(dir: vec3<f32>, coord: vec3<f32>, axis: u32) -> vec3<f32> {
+    let n = face_normal(axis, FACE_FRONT);
+    let t = dot(coord - globals.cam_pos, n) / dot(dir, n);
+    return globals.cam_pos + dir * t;
+}
+
+@vertex
+fn vx_ray_march(@location(0) pos: vec3<f32>) -> @builtin(position) vec4<f32> {
+    return vec4<f32>(pos.xy, 0.0, 1.0);
+}
+
+struct RayMarchOutput {
+    @location(0) color: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+}
+
+// Traces one camera ray per pixel through the chunk texture instead of
+// rasterizing faces. Texture and pixel footprints come from the rays
+// through the neighbouring pixels, derivatives do not work after the loop.
+@fragment
+fn fg_ray_march(@builtin(position) frag_pos: vec4<f32>) -> RayMarchOutput {
+    let resolution = vec2<f32>(globals.resolution);
+    let pixel_ndc = vec2<f32>(2.0, -2.0) / resolution;
+    let ndc = vec2<f32>(-1.0, 1.0) + frag_pos.xy * pixel_ndc;
+    let dir = camera_ray(ndc);
+
+    let hit = march(globals.cam_pos, normalize(dir));
+    if !hit.found {
+        discard;
+    }
+
+    // exactly on the crossed face and inside the cell along the others
+    let cell = vec3<f32>(hit.cell);
+    var coord = clamp(globals.cam_pos + normalize(dir) * hit.t, cell + 1e-3, cell + 1.0 - 1e-3);
+    let upper = vec3<f32>(dir < vec3<f32>(0.0));
+    if hit.axis == AXIS_X {
+        coord.x = cell.x + upper.x;
+    } else if hit.axis == AXIS_Y {
+        coord.y = cell.y + upper.y;
+    } else {
+        coord.z = cell.z + upper.z;
+    }
+
+    let coord_dx = ray_plane(camera_ray(ndc + vec2<f32>(pixel_ndc.x, 0.0)), coord, hit.axis) - coord;
+    let coord_dy = ray_plane(camera_ray(ndc + vec2<f32>(0.0, pixel_ndc.y)), coord, hit.axis) - coord;
+    let uv = face_uv(coord, hit.axis);
+    let color = shade(
+        coord,
+        hit.axis,
+        facing(coord, hit.axis),
+        abs(coord_dx.y) + abs(coord_dy.y),
+        abs(in_plane(coord_dx, hit.axis)) + abs(in_plane(coord_dy, hit.axis)),
+        uv,
+        face_uv(coord + coord_dx, hit.axis) - uv,
+        face_uv(coord + coord_dy, hit.axis) - uv,
+    );
+    if color.w == 0.0 {
+        discard;
+    }
+
+    let clip = globals.proj_view_mat * vec4<f32>(coord, 1.0);
+    var out: RayMarchOutput;
+    out.color = color;
+    out.depth = clip.z / clip.w;
+    return out;
+}
@@ -0,0 +1,108 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+const FOG: &str = "fog.wgsl";
+const MAIN: &str = "shader.wgsl";
+const BACKGROUND: &str = "background.wgsl";
+
+// Copies built into the binary, used by release builds and whenever the
+// source tree is not around
+const EMBEDDED: [(&str, &str); 3] = [
+    (FOG, include_str!("fog.wgsl")),
+    (MAIN, include_str!("shader.wgsl")),
+    (BACKGROUND, include_str!("background.wgsl")),
+];
+
+// Only checking every so often keeps file system calls out of most frames
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+pub struct ShaderSources {
+    pub main: String,
+    pub background: String,
+}
+
+// WGSL sources of the renderer. Debug builds read them from the source
+// tree, independent of the working directory, and notice when they change.
+// Release builds always use the embedded copies.
+pub struct ShaderFiles {
+    dir: PathBuf,
+    modified: Vec<Option<SystemTime>>,
+    last_poll: Instant,
+}
+
+impl ShaderFiles {
+    pub fn new() -> Self {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+        let mut files = Self {
+            dir,
+            modified: Vec::new(),
+            last_poll: Instant::now(),
+        };
+        files.modified = files.modified_times();
+        files
+    }
+
+    pub fn embedded() -> ShaderSources {
+        Self::combine(|name| Self::embedded_source(name).to_string())
+    }
+
+    pub fn load(&self) -> ShaderSources {
+        if !cfg!(debug_assertions) {
+            return Self::embedded();
+        }
+        Self::combine(|name| {
+            std::fs::read_to_string(self.dir.join(name)).unwrap_or_else(|e| {
+                log::warn!("Using embedded {name}, failed to read it from disk: {e}");
+                Self::embedded_source(name).to_string()
+            })
+        })
+    }
+
+    // True once after any of the files changed on disk
+    pub fn changed(&mut self) -> bool {
+        if !cfg!(debug_assertions) || self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = Instant::now();
+        let modified = self.modified_times();
+        if modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        true
+    }
+
+    fn modified_times(&self) -> Vec<Option<SystemTime>> {
+        EMBEDDED
+            .iter()
+            .map(|(name, _)| {
+                std::fs::metadata(self.dir.join(name))
+                    .and_then(|meta| meta.modified())
+                    .ok()
+            })
+            .collect()
+    }
+
+    // fog.wgsl is shared, both shaders start with it
+    fn combine(mut read: impl FnMut(&str) -> String) -> ShaderSources {
+        let fog = read(FOG);
+        ShaderSources {
+            main: fog.clone() + &read(MAIN),
+            background: fog + &read(BACKGROUND),
+        }
+    }
+
+    fn embedded_source(name: &str) -> &'static str {
+        EMBEDDED
+            .iter()
+            .find(|(file, _)| *file == name)
+            .map(|(_, source)| *source)
+            .unwrap_or_default()
+    }
+}
+
+impl Default for ShaderFiles {
+    fn default() -> Self {
+        Self::new()
+    }
+}
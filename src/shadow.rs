@@ -1,33 +1,151 @@
-use crate::{
-    camera::{self, Camera},
-    lattice,
-};
-
-pub fn directional(dir: cgmath::Vector3<f32>) -> cgmath::Matrix4<f32> {
-    use cgmath::*;
-    let view_mat = Matrix4::look_to_rh(Point3::origin() - dir * 128.0, dir, Vector3::unit_y());
-
-    let mut min_x = f32::INFINITY;
-    let mut max_x = f32::NEG_INFINITY;
-    let mut min_y = f32::INFINITY;
-    let mut max_y = f32::NEG_INFINITY;
+use cgmath::*;
+
+use crate::camera::{self, Camera};
+
+pub const CASCADE_COUNT: usize = 4;
+pub const MAP_SIZE: u32 = 2048;
+
+// blend between logarithmic (1.0) and uniform (0.0) split distances
+const SPLIT_LAMBDA: f32 = 0.75;
+
+pub struct Cascades {
+    pub matrices: [Matrix4<f32>; CASCADE_COUNT],
+    // far end of every cascade as distance along the view direction
+    pub splits: [f32; CASCADE_COUNT],
+}
+
+// One orthographic light projection per slice of the camera frustum. Each
+// slice is wrapped in a sphere so rotating the camera does not change the
+// projection size, and its centre is snapped to whole shadow map texels so
+// moving the camera does not make the edges shimmer.
+// `window` holds the corners of the render window in world space.
+pub fn cascades(camera: &Camera, dir: Vector3<f32>, window: &[Vector3<f32>]) -> Cascades {
+    // any up vector works as long as it is not parallel to the light
+    let up = if dir.y.abs() > 0.99 {
+        Vector3::unit_z()
+    } else {
+        Vector3::unit_y()
+    };
+    let view_mat = Matrix4::look_to_rh(Point3::origin(), dir, up);
+
+    // casters anywhere in the window have to land in every cascade
     let mut min_z = f32::INFINITY;
     let mut max_z = f32::NEG_INFINITY;
+    for &v in window {
+        let v = view_mat.transform_point(Point3::from_vec(v));
+        min_z = min_z.min(v.z);
+        max_z = max_z.max(v.z);
+    }
 
-    lattice::CORNERS.iter().for_each(|v| {
-        let mut v = Vector4::new(v.x, v.y, v.z, 1.0);
-        v = view_mat * v;
-        v /= v.w;
-
-        min_x = f32::min(min_x, v.x);
-        max_x = f32::max(max_x, v.x);
-        min_y = f32::min(min_y, v.y);
-        max_y = f32::max(max_y, v.y);
-        min_z = f32::min(min_z, v.z);
-        max_z = f32::max(max_z, v.z);
+    let inv_cam = camera
+        .proj_view_matrix()
+        .invert()
+        .unwrap_or_else(Matrix4::identity);
+    let rays = [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)].map(|(x, y)| {
+        let near = inv_cam.transform_point(Point3::new(x, y, 0.0));
+        let far = inv_cam.transform_point(Point3::new(x, y, 1.0));
+        (near, far - near)
     });
 
-    let proj_mat = ortho(min_x, max_x, min_y, max_y, min_z, max_z);
+    let (near, far) = (camera.znear, camera.zfar);
+    let split = |i: usize| {
+        let t = i as f32 / CASCADE_COUNT as f32;
+        let log = near * (far / near).powf(t);
+        let uniform = near + (far - near) * t;
+        SPLIT_LAMBDA * log + (1.0 - SPLIT_LAMBDA) * uniform
+    };
+
+    let mut matrices = [Matrix4::identity(); CASCADE_COUNT];
+    let mut splits = [0.0; CASCADE_COUNT];
+    for (i, (matrix, split_far)) in matrices.iter_mut().zip(&mut splits).enumerate() {
+        let (from, to) = (split(i), split(i + 1));
+        *split_far = to;
+
+        let corners: Vec<Point3<f32>> = [from, to]
+            .iter()
+            .flat_map(|d| {
+                let t = (d - near) / (far - near);
+                rays.iter().map(move |(origin, ray)| origin + ray * t)
+            })
+            .collect();
+        let center = Point3::centroid(&corners);
+        let radius = corners
+            .iter()
+            .map(|c| c.distance(center))
+            .fold(0.0, f32::max)
+            .ceil();
+
+        let texel = 2.0 * radius / MAP_SIZE as f32;
+        let center = view_mat.transform_point(center);
+        let (x, y) = (
+            (center.x / texel).floor() * texel,
+            (center.y / texel).floor() * texel,
+        );
+
+        // view space looks down -z, ortho takes distances in front of the eye
+        let proj_mat = ortho(
+            x - radius,
+            x + radius,
+            y - radius,
+            y + radius,
+            -max_z,
+            -min_z,
+        );
+        *matrix = camera::OPENGL_TO_WGPU_MATRIX * proj_mat * view_mat;
+    }
+
+    Cascades { matrices, splits }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // off the light's origin, so the depth range is not symmetric
+    fn window() -> Vec<Vector3<f32>> {
+        let mut corners = Vec::new();
+        for x in [100.0, 228.0] {
+            for y in [-32.0, 32.0] {
+                for z in [-300.0, -172.0] {
+                    corners.push(Vector3::new(x, y, z));
+                }
+            }
+        }
+        corners
+    }
+
+    // Depth of every window corner in every cascade, which has to be inside
+    // the light's clip space for the casters to end up in the shadow map
+    fn corner_depths(dir: Vector3<f32>) -> Vec<f32> {
+        let mut camera = Camera::new(1.0);
+        camera.position = Point3::new(164.0, 0.0, -236.0);
+        camera.pitch = Rad(0.4);
+        let window = window();
+        let cascades = cascades(&camera, dir.normalize(), &window);
+        cascades
+            .matrices
+            .iter()
+            .flat_map(|m| {
+                window
+                    .iter()
+                    .map(|&v| m.transform_point(Point3::from_vec(v)).z)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn window_fits_in_the_light_depth_range() {
+        for depth in corner_depths(Vector3::new(0.3, -1.0, 0.5)) {
+            assert!((-1e-4..=1.0 + 1e-4).contains(&depth), "depth {depth}");
+        }
+    }
 
-    camera::OPENGL_TO_WGPU_MATRIX * proj_mat * view_mat
+    #[test]
+    fn vertical_light_has_a_valid_projection() {
+        for dir in [Vector3::new(0.0, -1.0, 0.0), Vector3::new(0.0, 1.0, 0.0)] {
+            for depth in corner_depths(dir) {
+                assert!((-1e-4..=1.0 + 1e-4).contains(&depth), "depth {depth}");
+            }
+        }
+    }
 }
@@ -1,15 +1,14 @@
-use std::{num::NonZero, sync::Arc, time};
-
-use cgmath::InnerSpace;
-use wgpu::{util::DeviceExt, TextureView};
+use std::{sync::Arc, time};
 
 use crate::{
-    background,
+    block::{self, BlockId},
+    block_textures::{self, BlockTextures},
     camera::{Camera, CameraController},
-    chunk, chunk_storage,
-    globals::{self, GlobalsUniform},
-    lattice, shadow,
-    vertex::Vertex,
+    chunk_storage::{self, WindowSize},
+    generator, raycast,
+    renderer::{RenderMode, Renderer},
+    time_of_day::TimeOfDay,
+    vox, DataPaths,
 };
 
 pub struct State<'a> {
@@ -17,45 +16,37 @@ pub struct State<'a> {
     surface: wgpu::Surface<'a>,
     surface_config: wgpu::SurfaceConfiguration,
     adapter: wgpu::Adapter,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
-    pipeline: wgpu::RenderPipeline,
-    background_pipeline: wgpu::RenderPipeline,
-    shadow_pipeline: wgpu::RenderPipeline,
     window: Arc<winit::window::Window>,
-
-    bind_group: wgpu::BindGroup,
-    background_bind_group: wgpu::BindGroup,
-    shadow_bind_group: wgpu::BindGroup,
+    renderer: Renderer,
 
     camera: Camera,
     camera_controller: CameraController,
-    light_dir: cgmath::Vector3<f32>,
-    grid_lines: bool,
-    vertex_buf: wgpu::Buffer,
-    index_buf: wgpu::Buffer,
-    depth_texture: wgpu::Texture,
-    multisample_texture: wgpu::Texture,
-    background_vertices: wgpu::Buffer,
-    globals_buf: wgpu::Buffer,
-    chunk_texture: wgpu::Texture,
-    background_buf: wgpu::Buffer,
-    shadow_map: wgpu::Texture,
+    selected_block: BlockId,
+    time_of_day: TimeOfDay,
 
     chunks: chunk_storage::ChunkStorage,
+    window_origin: chunk_storage::ChunkPos,
 
     frame_count: u32,
-    start: time::Instant,
     last_render: time::Instant,
     last_print: time::Instant,
+    last_save: time::Instant,
     start_instant: time::Instant,
 }
 
 impl<'a> State<'a> {
     const INIT_WIDTH: u32 = 800;
     const INIT_HEIGHT: u32 = 600;
-
-    pub async fn new(window: winit::window::Window) -> Self {
+    const SEED: u32 = 2;
+    const MAX_CHUNKS_PER_FRAME: usize = 4;
+    const AUTOSAVE_INTERVAL: time::Duration = time::Duration::from_secs(30);
+    const REACH: f32 = 64.0;
+
+    pub async fn new(
+        window: winit::window::Window,
+        window_size: WindowSize,
+        paths: DataPaths,
+    ) -> Self {
         let window = Arc::new(window);
 
         let instance = wgpu::Instance::default();
@@ -74,359 +65,88 @@ impl<'a> State<'a> {
 
         let surface_config = Self::configure_surface(&surface, &adapter, &device);
 
-        let globals_buf = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("view matrix buffer"),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            size: std::mem::size_of::<globals::GlobalsUniform>() as u64,
-            mapped_at_creation: false,
-        });
-
-        let chunks = chunk_storage::ChunkStorage::new();
-
-        let chunk_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("chunk texture"),
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            size: wgpu::Extent3d {
-                width: lattice::XZ,
-                height: lattice::XZ,
-                depth_or_array_layers: lattice::Y,
-            },
-            mip_level_count: 1,
-            dimension: wgpu::TextureDimension::D3,
-            format: wgpu::TextureFormat::R32Uint,
-            sample_count: 1,
-            view_formats: &[],
-        });
-
-        let shadow_map = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("shadow map texture"),
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Depth32Float,
-            size: wgpu::Extent3d {
-                width: 4096,
-                height: 4096,
-                depth_or_array_layers: 1,
-            },
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
-            mip_level_count: 1,
-            sample_count: 1,
-            view_formats: &[],
-        });
-
-        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("bind group layout"),
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    ty: wgpu::BindingType::Texture {
-                        multisampled: false,
-                        sample_type: wgpu::TextureSampleType::Uint,
-                        view_dimension: wgpu::TextureViewDimension::D3,
-                    },
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    count: None,
-                },
-            ],
-        });
-
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("view matrix uniform"),
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: globals_buf.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::TextureView(
-                        &chunk_texture.create_view(&Default::default()),
-                    ),
-                },
-            ],
-        });
-
-        let shadow_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("shadow bind group layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    ty: wgpu::BindingType::Texture {
-                        multisampled: false,
-                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                    },
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    count: None,
-                }],
-            });
-
-        let shadow_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("shadow bind group"),
-            layout: &shadow_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(&shadow_map.create_view(
-                    &wgpu::TextureViewDescriptor {
-                        usage: Some(
-                            wgpu::TextureUsages::RENDER_ATTACHMENT
-                                | wgpu::TextureUsages::TEXTURE_BINDING,
-                        ),
-                        ..Default::default()
-                    },
-                )),
-            }],
-        });
-
-        let shader_source =
-            std::fs::read_to_string("./src/background.wgsl").expect("missing background.wgsl");
-        let background_shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("background shader module"),
-            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(shader_source.as_str())),
-        });
-
-        let shader_source =
-            std::fs::read_to_string("./src/shader.wgsl").expect("missing shader.wgsl");
-        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("main shader module"),
-            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(shader_source.as_str())),
-        });
-
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("render pipeline"),
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: wgpu::TextureFormat::Depth32Float,
-                depth_compare: Some(wgpu::CompareFunction::Less),
-                depth_write_enabled: Some(true),
-                bias: Default::default(),
-                stencil: Default::default(),
-            }),
-            layout: Some(
-                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                    label: Some("pipeline layout descriptor"),
-                    bind_group_layouts: &[
-                        Some(&bind_group_layout),
-                        Some(&shadow_bind_group_layout),
-                    ],
-                    ..Default::default()
-                }),
-            ),
-            multisample: wgpu::MultisampleState {
-                count: 4,
-                mask: 0xffffffff,
-                alpha_to_coverage_enabled: false,
-            },
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Cw,
-                cull_mode: None,
-                unclipped_depth: false,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                conservative: false,
-            },
-            vertex: wgpu::VertexState {
-                buffers: &[wgpu::VertexBufferLayout {
-                    step_mode: Default::default(),
-                    attributes: &Vertex::attributes(),
-                    array_stride: Vertex::stride(),
-                }],
-                compilation_options: Default::default(),
-                entry_point: Some("vx_main"),
-                module: &shader_module,
-            },
-            fragment: Some(wgpu::FragmentState {
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: surface_config.format,
-                    blend: None,
-                    write_mask: Default::default(),
-                })],
-                compilation_options: Default::default(),
-                entry_point: Some("fg_main"),
-                module: &shader_module,
-            }),
-            multiview_mask: None,
-            cache: None,
-        });
-
-        let (background_buf, background_bind_group_layout, background_bind_group) =
-            background::background(&device);
-
-        let background_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("background render pipeline"),
-            depth_stencil: None,
-            layout: Some(
-                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                    label: Some("pipeline layout descriptor"),
-                    bind_group_layouts: &[Some(&background_bind_group_layout)],
-                    ..Default::default()
-                }),
-            ),
-            multisample: Default::default(),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Cw,
-                cull_mode: Some(wgpu::Face::Back),
-                unclipped_depth: false,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                conservative: false,
-            },
-            vertex: wgpu::VertexState {
-                buffers: &[wgpu::VertexBufferLayout {
-                    step_mode: Default::default(),
-                    attributes: &wgpu::vertex_attr_array![0 => Float32x3],
-                    array_stride: std::mem::size_of::<[f32; 3]>() as u64,
-                }],
-                compilation_options: Default::default(),
-                entry_point: Some("vx_main"),
-                module: &background_shader_module,
-            },
-            fragment: Some(wgpu::FragmentState {
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: surface_config.format,
-                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                    write_mask: Default::default(),
-                })],
-                compilation_options: Default::default(),
-                entry_point: Some("fg_main"),
-                module: &background_shader_module,
-            }),
-            multiview_mask: None,
-            cache: None,
-        });
-
-        let shadow_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("shadow render pipeline"),
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: wgpu::TextureFormat::Depth32Float,
-                depth_compare: Some(wgpu::CompareFunction::Less),
-                depth_write_enabled: Some(true),
-                bias: Default::default(),
-                stencil: Default::default(),
-            }),
-            layout: Some(
-                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                    label: Some("shadow pipeline layout descriptor"),
-                    bind_group_layouts: &[Some(&bind_group_layout)],
-                    ..Default::default()
-                }),
-            ),
-            multisample: Default::default(),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Cw,
-                cull_mode: None,
-                unclipped_depth: false,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                conservative: false,
-            },
-            vertex: wgpu::VertexState {
-                buffers: &[wgpu::VertexBufferLayout {
-                    step_mode: Default::default(),
-                    attributes: &Vertex::attributes(),
-                    array_stride: Vertex::stride(),
-                }],
-                compilation_options: Default::default(),
-                entry_point: Some("vx_shadow"),
-                module: &shader_module,
-            },
-            fragment: Some(wgpu::FragmentState {
-                targets: &[],
-                compilation_options: Default::default(),
-                entry_point: Some("fg_shadow"),
-                module: &shader_module,
-            }),
-            multiview_mask: None,
-            cache: None,
-        });
-
-        let lattice = lattice::Lattice::new();
-
-        let vertex_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("vertex buffer"),
-            usage: wgpu::BufferUsages::VERTEX,
-            contents: bytemuck::cast_slice(&lattice.vertices),
-        });
-
-        let index_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("index buffer"),
-            usage: wgpu::BufferUsages::INDEX,
-            contents: bytemuck::cast_slice(&lattice.indices),
-        });
-
-        let depth_texture = Self::create_depth_texture(&device, &surface_config);
-        let multisample_texture = Self::create_multisample_texture(&device, &surface_config);
-
-        let background_vertices = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("background vertices"),
-            usage: wgpu::BufferUsages::VERTEX,
-            contents: bytemuck::cast_slice(&[
-                [-1.0f32, -1.0, 0.0],
-                [-1.0, 1.0, 0.0],
-                [1.0, -1.0, 0.0],
-                [-1.0, 1.0, 0.0],
-                [1.0, 1.0, 0.0],
-                [1.0, -1.0, 0.0],
-            ]),
-        });
+        let generator = generator::TerrainGenerator::new(Self::SEED);
+        let mut camera = Camera::new(Self::INIT_WIDTH as f32 / Self::INIT_HEIGHT as f32);
+        camera.position.y = generator.surface_height(0, 0) as f32 + 8.0;
+
+        let world_dir = std::path::absolute(&paths.world).unwrap_or(paths.world);
+        log::info!("World directory {}", world_dir.display());
+        let mut chunks = match chunk_storage::ChunkStorage::open(
+            &world_dir,
+            generator::TerrainGenerator::new(Self::SEED),
+        ) {
+            Ok(chunks) => chunks,
+            Err(e) => {
+                log::error!("Failed to open world {}: {e}", world_dir.display());
+                chunk_storage::ChunkStorage::new(generator)
+            }
+        };
+        let max_texture_size = device.limits().max_texture_dimension_3d;
+        if let Err(e) = window_size.check(max_texture_size) {
+            log::warn!("{e}");
+        }
+        let window_size = window_size.limit(max_texture_size);
+        chunks.set_window_size(window_size);
+        let window_origin = window_size.around(camera.position());
+        chunks.request_window(window_origin.x, window_origin.y, window_origin.z);
+
+        let mut renderer = Renderer::new(
+            device,
+            queue,
+            surface_config.format,
+            surface_config.width,
+            surface_config.height,
+            chunks.block_types(),
+        );
+        renderer.crosshair = true;
+        let texture_dir = std::path::absolute(&paths.textures).unwrap_or(paths.textures);
+        if texture_dir.is_dir() {
+            log::info!("Loading textures from {}", texture_dir.display());
+            match BlockTextures::load_dir(
+                &texture_dir,
+                chunks.block_types(),
+                block_textures::DEFAULT_TEXTURE_SIZE,
+            ) {
+                Ok(textures) => renderer.set_block_textures(&textures),
+                Err(e) => log::error!(
+                    "Failed to load textures from {}: {e}",
+                    texture_dir.display()
+                ),
+            }
+        } else {
+            log::info!(
+                "No texture directory at {}, using flat colours",
+                texture_dir.display()
+            );
+        }
 
         Self {
-            camera: Camera::new(Self::INIT_WIDTH as f32 / Self::INIT_HEIGHT as f32),
+            camera,
             camera_controller: CameraController::new(12.0, 0.002),
-            grid_lines: false,
+            selected_block: block::STONE,
+            time_of_day: TimeOfDay::default(),
 
             instance,
             surface,
             surface_config,
             adapter,
-            device,
-            queue,
-            pipeline,
-            background_pipeline,
-            shadow_pipeline,
             window,
-
-            bind_group,
-            background_bind_group,
-            shadow_bind_group,
-
-            vertex_buf,
-            index_buf,
-            depth_texture,
-            multisample_texture,
-            background_vertices,
-            globals_buf,
-            chunk_texture,
-            background_buf,
-
-            light_dir: cgmath::Vector3::new(0.0, -1.0, 0.0),
-            shadow_map,
+            renderer,
 
             chunks,
+            window_origin,
 
             frame_count: 0,
-            start: time::Instant::now(),
             last_render: time::Instant::now(),
             last_print: time::Instant::now(),
+            last_save: time::Instant::now(),
             start_instant: time::Instant::now(),
         }
     }
 
     pub fn recreate_surface(&mut self) {
         self.instance.create_surface(self.window.clone()).unwrap();
-        Self::configure_surface(&mut self.surface, &self.adapter, &self.device);
+        Self::configure_surface(&self.surface, &self.adapter, self.renderer.device());
     }
 
     pub fn configure_surface(
@@ -449,11 +169,8 @@ impl<'a> State<'a> {
 
     pub fn device_input(&mut self, event: winit::event::DeviceEvent) {
         use winit::event::DeviceEvent;
-        match event {
-            DeviceEvent::MouseMotion { delta: (dx, dy) } => {
-                self.camera_controller.process_mouse(dx as f32, dy as f32)
-            }
-            _ => (),
+        if let DeviceEvent::MouseMotion { delta: (dx, dy) } = event {
+            self.camera_controller.process_mouse(dx as f32, dy as f32)
         }
     }
 
@@ -471,8 +188,29 @@ impl<'a> State<'a> {
             } => {
                 let pressed = state == winit::event::ElementState::Pressed;
                 self.camera_controller.process_keyboard(code, pressed);
-                if code == winit::keyboard::KeyCode::F3 && pressed {
-                    self.grid_lines = !self.grid_lines;
+                if pressed {
+                    self.settings_key(code);
+                }
+                if let Some(block) = Self::block_key(code).filter(|_| pressed) {
+                    self.select_block(block);
+                }
+            }
+            WindowEvent::MouseInput {
+                state: winit::event::ElementState::Pressed,
+                button,
+                ..
+            } => match button {
+                winit::event::MouseButton::Left => self.remove_block(),
+                winit::event::MouseButton::Right => self.place_block(),
+                _ => (),
+            },
+            WindowEvent::DroppedFile(path) => {
+                // drop models a little in front of the camera
+                let target = self.camera.position() + self.camera.direction() * 16.0;
+                let offset = target.map(|v| v.floor() as i32);
+                match vox::import(&path, &mut self.chunks, offset) {
+                    Ok(count) => log::info!("Imported {count} voxels from {}", path.display()),
+                    Err(e) => log::error!("Failed to import {}: {e}", path.display()),
                 }
             }
             WindowEvent::MouseWheel {
@@ -485,7 +223,173 @@ impl<'a> State<'a> {
         }
     }
 
-    pub fn update(&mut self) {}
+    fn settings_key(&mut self, code: winit::keyboard::KeyCode) {
+        use winit::keyboard::KeyCode;
+        let renderer = &mut self.renderer;
+        match code {
+            KeyCode::F3 => renderer.grid_lines = !renderer.grid_lines,
+            KeyCode::F4 => {
+                renderer.ao = !renderer.ao;
+                log::info!(
+                    "Ambient occlusion {}",
+                    if renderer.ao { "on" } else { "off" }
+                );
+            }
+            KeyCode::F5 => {
+                renderer.shadow_pcf_radius =
+                    (renderer.shadow_pcf_radius + 1) % (Renderer::MAX_PCF_RADIUS + 1);
+                let size = renderer.shadow_pcf_radius * 2 + 1;
+                log::info!("Shadow filter {size}x{size}");
+            }
+            KeyCode::BracketLeft | KeyCode::BracketRight => {
+                let step = if code == KeyCode::BracketLeft {
+                    -0.1
+                } else {
+                    0.1
+                };
+                renderer.ao_strength = (renderer.ao_strength + step).clamp(0.0, 1.0);
+                log::info!("Ambient occlusion strength {:.1}", renderer.ao_strength);
+            }
+            KeyCode::Minus | KeyCode::Equal => {
+                let size = self.chunks.window_size();
+                let xz = if code == KeyCode::Minus {
+                    size.xz.saturating_sub(1)
+                } else {
+                    size.xz + 1
+                };
+                self.set_window_size(WindowSize::new(xz, size.y));
+            }
+            KeyCode::F8 => {
+                renderer.mode = match renderer.mode {
+                    RenderMode::Lattice => RenderMode::Mesh,
+                    RenderMode::Mesh => RenderMode::RayMarch,
+                    RenderMode::RayMarch => RenderMode::Lattice,
+                };
+                log::info!("Render mode {:?}", renderer.mode);
+            }
+            KeyCode::F7 => {
+                renderer.fog = !renderer.fog;
+                log::info!("Fog {}", if renderer.fog { "on" } else { "off" });
+            }
+            KeyCode::F6 => {
+                let time_of_day = &mut self.time_of_day;
+                time_of_day.paused = !time_of_day.paused;
+                log::info!(
+                    "Time of day {}",
+                    if time_of_day.paused {
+                        "paused"
+                    } else {
+                        "running"
+                    }
+                );
+            }
+            KeyCode::Comma | KeyCode::Period => {
+                let step = if code == KeyCode::Comma { -0.5 } else { 0.5 };
+                self.time_of_day.scrub(step);
+                let hours = self.time_of_day.hours();
+                log::info!(
+                    "Time of day {:02}:{:02}",
+                    hours as u32,
+                    (hours.fract() * 60.0) as u32
+                );
+            }
+            _ => (),
+        }
+    }
+
+    fn set_window_size(&mut self, size: WindowSize) {
+        let max_texture_size = self.renderer.device().limits().max_texture_dimension_3d;
+        if let Err(e) = size.check(max_texture_size) {
+            log::warn!("{e}");
+            return;
+        }
+        self.chunks.set_window_size(size);
+        let origin = size.around(self.camera.position());
+        self.chunks.request_window(origin.x, origin.y, origin.z);
+        self.window_origin = origin;
+        log::info!("Window size {}x{} chunks", size.xz, size.y);
+    }
+
+    fn block_key(code: winit::keyboard::KeyCode) -> Option<BlockId> {
+        use winit::keyboard::KeyCode;
+        let block = match code {
+            KeyCode::Digit1 => 1,
+            KeyCode::Digit2 => 2,
+            KeyCode::Digit3 => 3,
+            KeyCode::Digit4 => 4,
+            KeyCode::Digit5 => 5,
+            KeyCode::Digit6 => 6,
+            KeyCode::Digit7 => 7,
+            KeyCode::Digit8 => 8,
+            KeyCode::Digit9 => 9,
+            _ => return None,
+        };
+        Some(block)
+    }
+
+    fn select_block(&mut self, block: BlockId) {
+        let Some(block_type) = self.chunks.block_types().get(block) else {
+            return;
+        };
+        log::info!("Selected {}", block_type.name);
+        self.selected_block = block;
+    }
+
+    fn target(&self) -> Option<raycast::RayHit> {
+        raycast::raycast(
+            &self.chunks,
+            self.camera.position(),
+            self.camera.direction(),
+            Self::REACH,
+        )
+    }
+
+    fn remove_block(&mut self) {
+        if let Some(hit) = self.target() {
+            let p = hit.block;
+            self.chunks.set_block(p.x, p.y, p.z, block::AIR);
+        }
+    }
+
+    fn place_block(&mut self) {
+        let Some(hit) = self.target() else {
+            return;
+        };
+        let p = hit.adjacent;
+        // no face to place against from inside a block, and never into the camera
+        let camera = self.camera.position().map(|v| v.floor() as i32);
+        if hit.adjacent == hit.block || p == camera {
+            return;
+        }
+        self.chunks.set_block(p.x, p.y, p.z, self.selected_block);
+    }
+
+    pub fn update(&mut self) {
+        for result in self.chunks.finished_saves() {
+            Self::log_save(result);
+        }
+        if self.last_save.elapsed() > Self::AUTOSAVE_INTERVAL {
+            self.last_save = time::Instant::now();
+            if self.chunks.has_unsaved() {
+                self.chunks.save_in_background();
+            }
+        }
+    }
+
+    // Blocks until everything is on disk, for when the program exits
+    pub fn save_world(&mut self) {
+        self.last_save = time::Instant::now();
+        if self.chunks.has_unsaved() || self.chunks.is_saving() {
+            Self::log_save(self.chunks.save());
+        }
+    }
+
+    fn log_save(result: std::io::Result<usize>) {
+        match result {
+            Ok(count) => log::info!("Saved {count} chunks"),
+            Err(e) => log::error!("Failed to save world: {e}"),
+        }
+    }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         if new_size.width == 0 || new_size.height == 0 {
@@ -493,12 +397,28 @@ impl<'a> State<'a> {
         }
         self.surface_config.width = new_size.width;
         self.surface_config.height = new_size.height;
-        self.surface.configure(&self.device, &self.surface_config);
+        self.surface
+            .configure(self.renderer.device(), &self.surface_config);
 
         self.camera.resize(new_size.width, new_size.height);
-        self.multisample_texture =
-            Self::create_multisample_texture(&self.device, &self.surface_config);
-        self.depth_texture = Self::create_depth_texture(&self.device, &self.surface_config);
+        self.renderer.resize(new_size.width, new_size.height);
+    }
+
+    pub fn time_of_day(&self) -> &TimeOfDay {
+        &self.time_of_day
+    }
+
+    pub fn time_of_day_mut(&mut self) -> &mut TimeOfDay {
+        &mut self.time_of_day
+    }
+
+    fn apply_time_of_day(&mut self) {
+        let renderer = &mut self.renderer;
+        renderer.sun_dir = self.time_of_day.sun_direction();
+        renderer.light_dir = self.time_of_day.light_direction();
+        renderer.light_color = self.time_of_day.light_color();
+        renderer.ambient_color = self.time_of_day.ambient_color();
+        renderer.fog_color = self.time_of_day.fog_color();
     }
 
     pub fn width(&self) -> u32 {
@@ -525,190 +445,33 @@ impl<'a> State<'a> {
         self.camera_controller
             .update_camera(&mut self.camera, elapsed);
 
-        self.light_dir.x = f32::sin((now - self.start).as_secs_f32());
-        // self.light_dir.z = f32::cos((now - self.start).as_secs_f32());
-
-        self.queue.write_buffer(
-            &self.background_buf,
-            0,
-            bytemuck::bytes_of(&background::BackgroundUniform {
-                resolution: [self.width(), self.height()],
-                millis_elapsed: (now - self.start_instant).as_millis() as u32,
-                pitch: self.camera.pitch.0,
-                yaw: self.camera.yaw.0,
-                fovy: self.camera.fovy.0,
-            }),
-        );
+        self.time_of_day.advance(elapsed);
+        self.apply_time_of_day();
 
-        let cam_mat = self.camera.proj_view_matrix();
-        let dir = self.camera.direction();
-        let pos = self.camera.position();
-        let light = self.light_dir.normalize();
-        let globals = GlobalsUniform {
-            proj_view_mat: cam_mat.into(),
-            light_mat: shadow::directional(light).into(),
-            cam_dir: [dir.x, dir.y, dir.z],
-            cam_pos: [pos.x, pos.y, pos.z],
-            light_dir: [light.x, light.y, light.z],
-            grid_lines: self.grid_lines as u32,
-            _pad0: 0,
-            _pad1: 0,
-        };
-        self.queue
-            .write_buffer(&self.globals_buf, 0, bytemuck::cast_slice(&[globals]));
-        self.queue.write_texture(
-            self.chunk_texture.as_image_copy(),
-            bytemuck::cast_slice(&self.chunks.copy_to_render_buffer(0, 0, 0)),
-            wgpu::TexelCopyBufferLayout {
-                offset: 0,
-                bytes_per_row: Some(lattice::XZ * 4),
-                rows_per_image: Some(lattice::XZ),
-            },
-            self.chunk_texture.size(),
-        );
+        let window_origin = self.chunks.window_size().around(self.camera.position());
+        if window_origin != self.window_origin {
+            self.chunks
+                .request_window(window_origin.x, window_origin.y, window_origin.z);
+            self.window_origin = window_origin;
+        }
+        self.chunks.integrate(Self::MAX_CHUNKS_PER_FRAME);
+        self.renderer.poll_shaders();
+        self.renderer.upload_chunks(&mut self.chunks, &self.camera);
+        self.renderer.target = self.target();
 
         let out = match self.surface.get_current_texture() {
             wgpu::CurrentSurfaceTexture::Success(out) => out,
             cst => return Some(cst),
         };
         let out_view = out.texture.create_view(&Default::default());
-        let multisample_view = self.multisample_texture.create_view(&Default::default());
-        let depth_view = self.depth_texture.create_view(&Default::default());
-
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("command encoder"),
-            });
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("background render pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.5,
-                            g: 0.0,
-                            b: 0.5,
-                            a: 1.0,
-                        }),
-                        store: wgpu::StoreOp::Store,
-                    },
-                    resolve_target: None,
-                    view: &out_view,
-                    depth_slice: None,
-                })],
-                depth_stencil_attachment: None,
-                occlusion_query_set: None,
-                timestamp_writes: None,
-                multiview_mask: None,
-            });
-            render_pass.set_pipeline(&self.background_pipeline);
-            render_pass.set_bind_group(0, &self.background_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.background_vertices.slice(..));
-            render_pass.draw(0..6, 0..1);
-        }
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("shadow render pass"),
-                color_attachments: &[],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.shadow_map.create_view(&wgpu::TextureViewDescriptor {
-                        usage: Some(wgpu::TextureUsages::RENDER_ATTACHMENT),
-                        ..Default::default()
-                    }),
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: wgpu::StoreOp::Store,
-                    }),
-                    stencil_ops: None,
-                }),
-                occlusion_query_set: None,
-                timestamp_writes: None,
-                multiview_mask: None,
-            });
-            render_pass.set_pipeline(&self.shadow_pipeline);
-            render_pass.set_bind_group(0, &self.bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.vertex_buf.slice(..));
-            render_pass.set_index_buffer(self.index_buf.slice(..), wgpu::IndexFormat::Uint16);
-
-            render_pass.draw_indexed(0..lattice::NUM_INDICES as u32, 0, 0..1);
-        }
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("main render pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Load,
-                        store: wgpu::StoreOp::Discard,
-                    },
-                    resolve_target: Some(&out_view),
-                    view: &multisample_view,
-                    depth_slice: None,
-                })],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &depth_view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: wgpu::StoreOp::Store,
-                    }),
-                    stencil_ops: None,
-                }),
-                occlusion_query_set: None,
-                timestamp_writes: None,
-                multiview_mask: None,
-            });
-            render_pass.set_pipeline(&self.pipeline);
-            render_pass.set_bind_group(0, &self.bind_group, &[]);
-            render_pass.set_bind_group(1, &self.shadow_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.vertex_buf.slice(..));
-            render_pass.set_index_buffer(self.index_buf.slice(..), wgpu::IndexFormat::Uint16);
-
-            render_pass.draw_indexed(0..lattice::NUM_INDICES as u32, 0, 0..1);
-        }
+        self.renderer.render(
+            &out_view,
+            &self.camera,
+            (now - self.start_instant).as_millis() as u32,
+        );
 
-        self.queue.submit([encoder.finish()]);
         self.frame_count += 1;
         out.present();
         None
     }
-
-    fn create_depth_texture(
-        device: &wgpu::Device,
-        surface_config: &wgpu::SurfaceConfiguration,
-    ) -> wgpu::Texture {
-        device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("depth texture"),
-            size: wgpu::Extent3d {
-                width: surface_config.width,
-                height: surface_config.height,
-                depth_or_array_layers: 1,
-            },
-            format: wgpu::TextureFormat::Depth32Float,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            dimension: wgpu::TextureDimension::D2,
-            sample_count: 4,
-            mip_level_count: 1,
-            view_formats: &[],
-        })
-    }
-
-    fn create_multisample_texture(
-        device: &wgpu::Device,
-        surface_config: &wgpu::SurfaceConfiguration,
-    ) -> wgpu::Texture {
-        device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Multisample texture"),
-            dimension: wgpu::TextureDimension::D2,
-            format: surface_config.format,
-            size: wgpu::Extent3d {
-                width: surface_config.width,
-                height: surface_config.height,
-                ..Default::default()
-            },
-            mip_level_count: 1,
-            sample_count: 4,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            view_formats: &[],
-        })
-    }
 }
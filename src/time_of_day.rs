@@ -0,0 +1,202 @@
+use std::time::Duration;
+
+use cgmath::{InnerSpace, Vector3};
+
+// Position of the sun and moon and the light they give over a day. The sun
+// follows the celestial equator, so the day is split evenly into day and
+// night and the latitude only changes how high the sun gets at noon.
+#[derive(Debug, Clone)]
+pub struct TimeOfDay {
+    // real seconds for one full day
+    pub day_length: f32,
+    // degrees north of the equator, negative for the southern hemisphere
+    pub latitude: f32,
+    pub paused: bool,
+    // fraction of the day, 0 is midnight and 0.5 noon
+    time: f32,
+}
+
+impl TimeOfDay {
+    pub const DEFAULT_DAY_LENGTH: f32 = 600.0;
+    pub const DEFAULT_LATITUDE: f32 = 40.0;
+
+    pub fn new(day_length: f32, latitude: f32) -> Self {
+        Self {
+            day_length,
+            latitude,
+            paused: false,
+            time: 0.375,
+        }
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn set_time(&mut self, time: f32) {
+        if time.is_finite() {
+            self.time = time.rem_euclid(1.0);
+        }
+    }
+
+    pub fn hours(&self) -> f32 {
+        self.time * 24.0
+    }
+
+    pub fn set_hours(&mut self, hours: f32) {
+        self.set_time(hours / 24.0);
+    }
+
+    // Moves the time by `hours`, even while paused
+    pub fn scrub(&mut self, hours: f32) {
+        self.set_hours(self.hours() + hours);
+    }
+
+    pub fn advance(&mut self, dt: Duration) {
+        if !self.paused && self.day_length > 0.0 {
+            self.set_time(self.time + dt.as_secs_f32() / self.day_length);
+        }
+    }
+
+    // Unit vector pointing at the sun, +x is east and -z north
+    pub fn sun_direction(&self) -> Vector3<f32> {
+        let hour_angle = (self.time - 0.5) * std::f32::consts::TAU;
+        let latitude = self.latitude.clamp(-90.0, 90.0).to_radians();
+        Vector3::new(
+            -hour_angle.sin(),
+            latitude.cos() * hour_angle.cos(),
+            latitude.sin() * hour_angle.cos(),
+        )
+        .normalize()
+    }
+
+    // Always full and opposite the sun
+    pub fn moon_direction(&self) -> Vector3<f32> {
+        -self.sun_direction()
+    }
+
+    // Direction the light travels in, from whichever of sun and moon is up
+    pub fn light_direction(&self) -> Vector3<f32> {
+        let sun = self.sun_direction();
+        if sun.y >= 0.0 {
+            -sun
+        } else {
+            sun
+        }
+    }
+
+    pub fn light_color(&self) -> Vector3<f32> {
+        let sun = self.sun_direction();
+        let (elevation, color) = if sun.y >= 0.0 {
+            // reddens as the light passes through more atmosphere
+            let t = smoothstep(0.0, 0.3, sun.y);
+            let low = Vector3::new(1.0, 0.55, 0.3);
+            let high = Vector3::new(1.0, 0.97, 0.9);
+            (sun.y, low + (high - low) * t)
+        } else {
+            (-sun.y, Vector3::new(0.15, 0.18, 0.28))
+        };
+        color * smoothstep(0.0, 0.1, elevation)
+    }
+
+    pub fn ambient_color(&self) -> Vector3<f32> {
+        let elevation = self.sun_direction().y;
+        let night = Vector3::new(0.04, 0.05, 0.09);
+        let day = Vector3::new(0.3, 0.32, 0.36);
+        let sunset = Vector3::new(0.12, 0.05, 0.0);
+        let dusk = 1.0 - smoothstep(0.0, 0.3, elevation.abs());
+        night + (day - night) * smoothstep(-0.1, 0.2, elevation) + sunset * dusk
+    }
+
+    // Roughly the colour of the sky at the horizon
+    pub fn fog_color(&self) -> Vector3<f32> {
+        let elevation = self.sun_direction().y;
+        let night = Vector3::new(0.004, 0.006, 0.018);
+        let day = Vector3::new(0.7, 0.82, 0.95);
+        let sunset = Vector3::new(1.0, 0.45, 0.2);
+        let dusk = 1.0 - smoothstep(0.0, 0.35, elevation.abs());
+        let color = day * smoothstep(-0.15, 0.1, elevation);
+        let color = color + (sunset - color) * (0.5 * dusk);
+        Vector3::new(
+            color.x.max(night.x),
+            color.y.max(night.y),
+            color.z.max(night.z),
+        )
+    }
+}
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_DAY_LENGTH, Self::DEFAULT_LATITUDE)
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: f32) -> TimeOfDay {
+        let mut time_of_day = TimeOfDay::default();
+        time_of_day.set_time(time);
+        time_of_day
+    }
+
+    #[test]
+    fn sun_is_highest_at_noon_and_down_at_midnight() {
+        let noon = at(0.5).sun_direction();
+        assert!(noon.y > 0.0);
+        for i in 0..100 {
+            assert!(at(i as f32 / 100.0).sun_direction().y <= noon.y);
+        }
+        assert!(at(0.0).sun_direction().y < 0.0);
+        // the moon is up instead
+        assert!(at(0.0).moon_direction().y > 0.0);
+    }
+
+    #[test]
+    fn light_always_points_down() {
+        for i in 0..=200 {
+            let time_of_day = at(i as f32 / 200.0);
+            let light = time_of_day.light_direction();
+            // horizontal only while sun and moon are both on the horizon
+            assert!(light.y <= 0.0, "light {light:?} at {}", time_of_day.time());
+            assert!((light.magnitude() - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn time_wraps_around() {
+        let mut time_of_day = at(-0.25);
+        assert!((time_of_day.time() - 0.75).abs() < 1e-6);
+        time_of_day.set_time(1.5);
+        assert!((time_of_day.time() - 0.5).abs() < 1e-6);
+        time_of_day.set_time(f32::NAN);
+        assert!((time_of_day.time() - 0.5).abs() < 1e-6);
+
+        time_of_day.set_hours(23.0);
+        time_of_day.scrub(2.0);
+        assert!((time_of_day.hours() - 1.0).abs() < 1e-4);
+        time_of_day.scrub(-3.0);
+        assert!((time_of_day.hours() - 22.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn advance_stops_while_paused() {
+        let mut time_of_day = TimeOfDay::new(100.0, 40.0);
+        time_of_day.set_time(0.9);
+        time_of_day.advance(Duration::from_secs(20));
+        assert!((time_of_day.time() - 0.1).abs() < 1e-5);
+
+        time_of_day.paused = true;
+        time_of_day.advance(Duration::from_secs(20));
+        assert!((time_of_day.time() - 0.1).abs() < 1e-5);
+        // scrubbing still works while paused
+        time_of_day.scrub(2.4);
+        assert!((time_of_day.time() - 0.2).abs() < 1e-5);
+    }
+}
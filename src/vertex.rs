@@ -21,3 +21,19 @@ impl Vertex {
         ]
     }
 }
+
+// Index buffer contents, in 16 bits unless there are too many vertices
+pub fn index_data(vertex_count: usize, indices: &[u32]) -> (Vec<u8>, wgpu::IndexFormat) {
+    if vertex_count <= u16::MAX as usize + 1 {
+        let indices: Vec<u16> = indices.iter().map(|&i| i as u16).collect();
+        (
+            bytemuck::cast_slice(&indices).to_vec(),
+            wgpu::IndexFormat::Uint16,
+        )
+    } else {
+        (
+            bytemuck::cast_slice(indices).to_vec(),
+            wgpu::IndexFormat::Uint32,
+        )
+    }
+}
@@ -0,0 +1,670 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use crate::block::{self, BlockId};
+use crate::chunk_storage::{BlockPos, ChunkStorage};
+
+#[derive(Debug)]
+pub enum VoxError {
+    Io(io::Error),
+    NotVox,
+    UnsupportedVersion(i32),
+    Truncated(&'static str),
+    Invalid(String),
+}
+
+impl fmt::Display for VoxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VoxError::Io(e) => write!(f, "{e}"),
+            VoxError::NotVox => write!(f, "not a MagicaVoxel file"),
+            VoxError::UnsupportedVersion(v) => write!(f, "unsupported .vox version {v}"),
+            VoxError::Truncated(what) => write!(f, "file ends inside {what}"),
+            VoxError::Invalid(msg) => write!(f, "{msg}"),
+        }
+    }
+}
+
+impl std::error::Error for VoxError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            VoxError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for VoxError {
+    fn from(e: io::Error) -> Self {
+        VoxError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VoxModel {
+    pub size: [u32; 3],
+    // x, y, z and palette index, in MagicaVoxel's Z-up coordinates
+    pub voxels: Vec<[u8; 4]>,
+}
+
+// Rows of a signed permutation matrix followed by a translation, applied to
+// voxel positions relative to the model centre.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VoxTransform {
+    pub rotation: [[i32; 3]; 3],
+    pub translation: [i32; 3],
+}
+
+impl VoxTransform {
+    pub const IDENTITY: Self = Self {
+        rotation: [[1, 0, 0], [0, 1, 0], [0, 0, 1]],
+        translation: [0, 0, 0],
+    };
+
+    fn apply(&self, v: [i32; 3]) -> [i32; 3] {
+        let r = &self.rotation;
+        let mut out = self.translation;
+        for (i, o) in out.iter_mut().enumerate() {
+            *o += r[i][0] * v[0] + r[i][1] * v[1] + r[i][2] * v[2];
+        }
+        out
+    }
+
+    fn then(&self, parent: &Self) -> Self {
+        let mut rotation = [[0; 3]; 3];
+        for (i, row) in rotation.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..3)
+                    .map(|k| parent.rotation[i][k] * self.rotation[k][j])
+                    .sum();
+            }
+        }
+        Self {
+            rotation,
+            translation: parent.apply(self.translation),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VoxInstance {
+    pub model: usize,
+    pub transform: VoxTransform,
+}
+
+#[derive(Debug, Clone)]
+pub struct VoxScene {
+    pub models: Vec<VoxModel>,
+    pub instances: Vec<VoxInstance>,
+    // RGBA colour of every palette index, index 0 is unused
+    pub palette: [[u8; 4]; 256],
+}
+
+pub fn load(path: impl AsRef<Path>) -> Result<VoxScene, VoxError> {
+    parse(&std::fs::read(path)?)
+}
+
+// Reads a file into the storage with its minimum corner at `offset`.
+// Returns the number of voxels written.
+pub fn import(
+    path: impl AsRef<Path>,
+    storage: &mut ChunkStorage,
+    offset: BlockPos,
+) -> Result<usize, VoxError> {
+    Ok(load(path)?.stamp(storage, offset))
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize, what: &'static str) -> Result<&'a [u8], VoxError> {
+        if self.bytes.len() < len {
+            return Err(VoxError::Truncated(what));
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn i32(&mut self, what: &'static str) -> Result<i32, VoxError> {
+        Ok(i32::from_le_bytes(self.take(4, what)?.try_into().unwrap()))
+    }
+
+    fn len(&mut self, what: &'static str) -> Result<usize, VoxError> {
+        let v = self.i32(what)?;
+        usize::try_from(v).map_err(|_| VoxError::Invalid(format!("negative length in {what}")))
+    }
+
+    fn string(&mut self, what: &'static str) -> Result<String, VoxError> {
+        let len = self.len(what)?;
+        Ok(String::from_utf8_lossy(self.take(len, what)?).into_owned())
+    }
+
+    fn dict(&mut self, what: &'static str) -> Result<HashMap<String, String>, VoxError> {
+        let count = self.len(what)?;
+        let mut dict = HashMap::new();
+        for _ in 0..count {
+            let key = self.string(what)?;
+            let value = self.string(what)?;
+            dict.insert(key, value);
+        }
+        Ok(dict)
+    }
+}
+
+enum Node {
+    Transform { child: i32, transform: VoxTransform },
+    Group { children: Vec<i32> },
+    Shape { models: Vec<usize> },
+}
+
+fn parse_rotation(bits: u8) -> Result<[[i32; 3]; 3], VoxError> {
+    let first = (bits & 3) as usize;
+    let second = ((bits >> 2) & 3) as usize;
+    if first > 2 || second > 2 || first == second {
+        return Err(VoxError::Invalid(format!("invalid rotation {bits}")));
+    }
+    let third = 3 - first - second;
+    let mut rotation = [[0; 3]; 3];
+    for (row, (column, sign_bit)) in [(first, 4), (second, 5), (third, 6)]
+        .into_iter()
+        .enumerate()
+    {
+        rotation[row][column] = if bits & (1 << sign_bit) != 0 { -1 } else { 1 };
+    }
+    Ok(rotation)
+}
+
+fn parse_frame(dict: &HashMap<String, String>) -> Result<VoxTransform, VoxError> {
+    let mut transform = VoxTransform::IDENTITY;
+    if let Some(t) = dict.get("_t") {
+        let values: Vec<i32> = t
+            .split_whitespace()
+            .map(|v| v.parse::<i32>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| VoxError::Invalid(format!("invalid translation {t:?}")))?;
+        transform.translation = values
+            .try_into()
+            .map_err(|_| VoxError::Invalid(format!("invalid translation {t:?}")))?;
+    }
+    if let Some(r) = dict.get("_r") {
+        let bits = r
+            .parse::<u8>()
+            .map_err(|_| VoxError::Invalid(format!("invalid rotation {r:?}")))?;
+        transform.rotation = parse_rotation(bits)?;
+    }
+    Ok(transform)
+}
+
+pub fn parse(bytes: &[u8]) -> Result<VoxScene, VoxError> {
+    let mut r = Reader { bytes };
+    if r.take(4, "header").map_err(|_| VoxError::NotVox)? != b"VOX " {
+        return Err(VoxError::NotVox);
+    }
+    let version = r.i32("header")?;
+    if version < 150 {
+        return Err(VoxError::UnsupportedVersion(version));
+    }
+
+    let id = r.take(4, "MAIN chunk")?;
+    if id != b"MAIN" {
+        return Err(VoxError::Invalid("missing MAIN chunk".to_string()));
+    }
+    let content = r.len("MAIN chunk")?;
+    let children = r.len("MAIN chunk")?;
+    r.take(content, "MAIN chunk")?;
+    let mut r = Reader {
+        bytes: r.take(children, "MAIN chunk")?,
+    };
+
+    let mut models = Vec::new();
+    let mut size = None;
+    let mut palette = default_palette();
+    let mut nodes = HashMap::new();
+
+    while !r.bytes.is_empty() {
+        let id: [u8; 4] = r.take(4, "chunk header")?.try_into().unwrap();
+        let content = r.len("chunk header")?;
+        let children = r.len("chunk header")?;
+        let mut c = Reader {
+            bytes: r.take(content, "chunk content")?,
+        };
+        r.take(children, "chunk children")?;
+
+        match &id {
+            b"SIZE" => {
+                let mut dims = [0; 3];
+                for d in dims.iter_mut() {
+                    *d = c.len("SIZE chunk")? as u32;
+                }
+                size = Some(dims);
+            }
+            b"XYZI" => {
+                let size = size.take().ok_or_else(|| {
+                    VoxError::Invalid("XYZI chunk without a preceding SIZE chunk".to_string())
+                })?;
+                let count = c.len("XYZI chunk")?;
+                let data = c.take(count * 4, "XYZI chunk")?;
+                let voxels: Vec<[u8; 4]> = data
+                    .chunks_exact(4)
+                    .map(|v| v.try_into().unwrap())
+                    .collect();
+                if let Some(v) = voxels
+                    .iter()
+                    .find(|v| (0..3).any(|i| v[i] as u32 >= size[i]))
+                {
+                    return Err(VoxError::Invalid(format!(
+                        "voxel {:?} outside of model size {size:?}",
+                        &v[..3]
+                    )));
+                }
+                models.push(VoxModel { size, voxels });
+            }
+            b"RGBA" => {
+                let colors = c.take(256 * 4, "RGBA chunk")?;
+                // palette entry i holds the colour of index i + 1
+                for (i, color) in colors.chunks_exact(4).take(255).enumerate() {
+                    palette[i + 1] = color.try_into().unwrap();
+                }
+            }
+            b"nTRN" => {
+                let node = c.i32("nTRN chunk")?;
+                c.dict("nTRN chunk")?;
+                let child = c.i32("nTRN chunk")?;
+                c.i32("nTRN chunk")?;
+                c.i32("nTRN chunk")?;
+                let frames = c.len("nTRN chunk")?;
+                let mut transform = VoxTransform::IDENTITY;
+                for frame in 0..frames {
+                    let dict = c.dict("nTRN chunk")?;
+                    if frame == 0 {
+                        transform = parse_frame(&dict)?;
+                    }
+                }
+                nodes.insert(node, Node::Transform { child, transform });
+            }
+            b"nGRP" => {
+                let node = c.i32("nGRP chunk")?;
+                c.dict("nGRP chunk")?;
+                let count = c.len("nGRP chunk")?;
+                let children = (0..count)
+                    .map(|_| c.i32("nGRP chunk"))
+                    .collect::<Result<_, _>>()?;
+                nodes.insert(node, Node::Group { children });
+            }
+            b"nSHP" => {
+                let node = c.i32("nSHP chunk")?;
+                c.dict("nSHP chunk")?;
+                let count = c.len("nSHP chunk")?;
+                let mut shape_models = Vec::new();
+                for _ in 0..count {
+                    shape_models.push(c.len("nSHP chunk")?);
+                    c.dict("nSHP chunk")?;
+                }
+                nodes.insert(
+                    node,
+                    Node::Shape {
+                        models: shape_models,
+                    },
+                );
+            }
+            _ => {}
+        }
+    }
+
+    // without a scene graph every model would sit at the origin, so they are
+    // laid out side by side along x with a voxel of space in between
+    let instances = if nodes.is_empty() {
+        let mut x = 0;
+        models
+            .iter()
+            .enumerate()
+            .map(|(model, m)| {
+                let mut transform = VoxTransform::IDENTITY;
+                transform.translation[0] = x + (m.size[0] / 2) as i32;
+                x += m.size[0] as i32 + 1;
+                VoxInstance { model, transform }
+            })
+            .collect()
+    } else {
+        let mut instances = Vec::new();
+        collect_instances(&nodes, 0, VoxTransform::IDENTITY, 0, &mut instances)?;
+        instances
+    };
+    if let Some(i) = instances.iter().find(|i| i.model >= models.len()) {
+        return Err(VoxError::Invalid(format!(
+            "scene references missing model {}",
+            i.model
+        )));
+    }
+
+    Ok(VoxScene {
+        models,
+        instances,
+        palette,
+    })
+}
+
+fn collect_instances(
+    nodes: &HashMap<i32, Node>,
+    id: i32,
+    transform: VoxTransform,
+    depth: usize,
+    instances: &mut Vec<VoxInstance>,
+) -> Result<(), VoxError> {
+    // scene graphs are trees, anything deeper than this is a cycle
+    if depth > 256 {
+        return Err(VoxError::Invalid(
+            "scene graph contains a cycle".to_string(),
+        ));
+    }
+    let node = nodes
+        .get(&id)
+        .ok_or_else(|| VoxError::Invalid(format!("scene references missing node {id}")))?;
+    match node {
+        Node::Transform {
+            child,
+            transform: local,
+        } => collect_instances(nodes, *child, local.then(&transform), depth + 1, instances)?,
+        Node::Group { children } => {
+            for &child in children {
+                collect_instances(nodes, child, transform, depth + 1, instances)?;
+            }
+        }
+        Node::Shape { models } => {
+            instances.extend(models.iter().map(|&model| VoxInstance { model, transform }));
+        }
+    }
+    Ok(())
+}
+
+// MagicaVoxel's built-in palette: a 6×6×6 colour cube followed by red,
+// green, blue and grey ramps.
+fn default_palette() -> [[u8; 4]; 256] {
+    const CUBE: [u8; 6] = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+    const RAMP: [u8; 10] = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+
+    let mut palette = [[0; 4]; 256];
+    let mut i = 1;
+    for r in CUBE {
+        for g in CUBE {
+            for b in CUBE {
+                if i < 216 {
+                    palette[i] = [r, g, b, 0xff];
+                    i += 1;
+                }
+            }
+        }
+    }
+    for channel in [2, 1, 0] {
+        for v in RAMP {
+            let mut color = [0, 0, 0, 0xff];
+            color[channel] = v;
+            palette[i] = color;
+            i += 1;
+        }
+    }
+    for v in RAMP {
+        palette[i] = [v, v, v, 0xff];
+        i += 1;
+    }
+    palette
+}
+
+fn srgb_to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+impl VoxScene {
+    // Position of a voxel in world space relative to the scene origin.
+    // MagicaVoxel is Z-up, so its (x, y, z) becomes (x, z, -y).
+    fn world_voxel(instance: &VoxInstance, model: &VoxModel, v: [u8; 4]) -> BlockPos {
+        let centre = model.size.map(|s| (s / 2) as i32);
+        let local = [
+            v[0] as i32 - centre[0],
+            v[1] as i32 - centre[1],
+            v[2] as i32 - centre[2],
+        ];
+        let [x, y, z] = instance.transform.apply(local);
+        BlockPos::new(x, z, -y)
+    }
+
+    pub fn voxel_count(&self) -> usize {
+        self.instances
+            .iter()
+            .map(|i| self.models[i.model].voxels.len())
+            .sum()
+    }
+
+    // Inclusive world-space bounds relative to the scene origin
+    pub fn bounds(&self) -> Option<(BlockPos, BlockPos)> {
+        let mut bounds: Option<(BlockPos, BlockPos)> = None;
+        for instance in &self.instances {
+            let model = &self.models[instance.model];
+            for &v in &model.voxels {
+                let p = Self::world_voxel(instance, model, v);
+                bounds = Some(match bounds {
+                    None => (p, p),
+                    Some((lo, hi)) => (
+                        BlockPos::new(lo.x.min(p.x), lo.y.min(p.y), lo.z.min(p.z)),
+                        BlockPos::new(hi.x.max(p.x), hi.y.max(p.y), hi.z.max(p.z)),
+                    ),
+                });
+            }
+        }
+        bounds
+    }
+
+    pub fn used_colors(&self) -> Vec<u8> {
+        let mut used = [false; 256];
+        for instance in &self.instances {
+            for v in &self.models[instance.model].voxels {
+                used[v[3] as usize] = true;
+            }
+        }
+        (1..=255u8).filter(|&i| used[i as usize]).collect()
+    }
+
+    // Maps every used palette index to a block type, registering a new type
+    // per colour and falling back to the closest existing colour once the
+    // registry is full.
+    pub fn block_types(&self, registry: &mut block::BlockRegistry) -> [BlockId; 256] {
+        let mut blocks = [block::AIR; 256];
+        for i in self.used_colors() {
+            let color = self.palette[i as usize];
+            let name = format!("vox #{:02x}{:02x}{:02x}", color[0], color[1], color[2]);
+            let linear = [
+                srgb_to_linear(color[0]),
+                srgb_to_linear(color[1]),
+                srgb_to_linear(color[2]),
+                1.0,
+            ];
+            blocks[i as usize] = registry
+                .find(&name)
+                .or_else(|| registry.register(&name, linear))
+                .unwrap_or_else(|| registry.closest(linear));
+        }
+        blocks
+    }
+
+    // Writes every voxel into the storage so that the minimum corner of the
+    // scene lands on `offset`. Returns the number of voxels written.
+    pub fn stamp(&self, storage: &mut ChunkStorage, offset: BlockPos) -> usize {
+        let Some((min, _)) = self.bounds() else {
+            return 0;
+        };
+        let blocks = self.block_types(storage.block_types_mut());
+
+        let mut count = 0;
+        for instance in &self.instances {
+            let model = &self.models[instance.model];
+            for &v in &model.voxels {
+                let p = Self::world_voxel(instance, model, v) - min + offset;
+                storage.set_block(p.x, p.y, p.z, blocks[v[3] as usize]);
+                count += 1;
+            }
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::FlatGenerator;
+
+    fn chunk(id: &[u8; 4], content: &[u8], children: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend((content.len() as i32).to_le_bytes());
+        bytes.extend((children.len() as i32).to_le_bytes());
+        bytes.extend(content);
+        bytes.extend(children);
+        bytes
+    }
+
+    fn ints(values: &[i32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn dict(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut bytes = ints(&[entries.len() as i32]);
+        for (key, value) in entries {
+            for s in [key, value] {
+                bytes.extend(ints(&[s.len() as i32]));
+                bytes.extend(s.as_bytes());
+            }
+        }
+        bytes
+    }
+
+    fn model(size: [i32; 3], voxels: &[[u8; 4]]) -> Vec<u8> {
+        let mut bytes = chunk(b"SIZE", &ints(&size), &[]);
+        let mut xyzi = ints(&[voxels.len() as i32]);
+        xyzi.extend(voxels.iter().flatten());
+        bytes.extend(chunk(b"XYZI", &xyzi, &[]));
+        bytes
+    }
+
+    fn vox(children: &[u8]) -> Vec<u8> {
+        let mut bytes = b"VOX ".to_vec();
+        bytes.extend(ints(&[150]));
+        bytes.extend(chunk(b"MAIN", &[], children));
+        bytes
+    }
+
+    fn transform(node: i32, child: i32, frame: &[(&str, &str)]) -> Vec<u8> {
+        let mut content = ints(&[node]);
+        content.extend(dict(&[]));
+        content.extend(ints(&[child, -1, 0, 1]));
+        content.extend(dict(frame));
+        chunk(b"nTRN", &content, &[])
+    }
+
+    fn group(node: i32, children: &[i32]) -> Vec<u8> {
+        let mut content = ints(&[node]);
+        content.extend(dict(&[]));
+        content.extend(ints(&[children.len() as i32]));
+        content.extend(ints(children));
+        chunk(b"nGRP", &content, &[])
+    }
+
+    fn shape(node: i32, model: i32) -> Vec<u8> {
+        let mut content = ints(&[node]);
+        content.extend(dict(&[]));
+        content.extend(ints(&[1, model]));
+        content.extend(dict(&[]));
+        chunk(b"nSHP", &content, &[])
+    }
+
+    fn world_voxels(scene: &VoxScene) -> Vec<BlockPos> {
+        scene
+            .instances
+            .iter()
+            .flat_map(|instance| {
+                let model = &scene.models[instance.model];
+                model
+                    .voxels
+                    .iter()
+                    .map(|&v| VoxScene::world_voxel(instance, model, v))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn single_model_is_read_and_stamped_y_up() {
+        let scene = parse(&vox(&model([2, 3, 4], &[[0, 0, 0, 1], [1, 2, 3, 2]]))).unwrap();
+        assert_eq!(scene.models.len(), 1);
+        assert_eq!(scene.models[0].size, [2, 3, 4]);
+        assert_eq!(scene.models[0].voxels, [[0, 0, 0, 1], [1, 2, 3, 2]]);
+        assert_eq!(scene.voxel_count(), 2);
+        assert_eq!(scene.used_colors(), [1, 2]);
+
+        let mut storage = ChunkStorage::new(FlatGenerator::new(0));
+        let offset = BlockPos::new(0, 200, 0);
+        assert_eq!(scene.stamp(&mut storage, offset), 2);
+        // MagicaVoxel's z is up and its y points away from the viewer
+        let blocks = scene.block_types(storage.block_types_mut());
+        assert_eq!(storage.get_block(0, 200, 2), blocks[1]);
+        assert_eq!(storage.get_block(1, 203, 0), blocks[2]);
+        assert_ne!(blocks[1], blocks[2]);
+    }
+
+    #[test]
+    fn scene_graph_transforms_are_applied() {
+        // a group translated up 5 holding one plain model and one that is
+        // rotated a quarter turn around z and moved 10 along x
+        let mut children = model([1, 1, 1], &[[0, 0, 0, 1]]);
+        children.extend(model([3, 1, 1], &[[2, 0, 0, 1]]));
+        children.extend(transform(0, 1, &[("_t", "0 0 5")]));
+        children.extend(group(1, &[2, 4]));
+        children.extend(transform(2, 3, &[]));
+        children.extend(shape(3, 0));
+        children.extend(transform(4, 5, &[("_t", "10 0 0"), ("_r", "33")]));
+        children.extend(shape(5, 1));
+        let scene = parse(&vox(&children)).unwrap();
+
+        assert_eq!(scene.instances.len(), 2);
+        assert_eq!(scene.instances[0].transform.translation, [0, 0, 5]);
+        let rotated = scene.instances[1].transform;
+        assert_eq!(rotated.rotation, [[0, 1, 0], [-1, 0, 0], [0, 0, 1]]);
+        assert_eq!(rotated.translation, [10, 0, 5]);
+        // (1, 0, 0) from the centre turns into (0, -1, 0)
+        assert_eq!(
+            world_voxels(&scene),
+            [BlockPos::new(0, 5, 0), BlockPos::new(10, 5, 1)]
+        );
+    }
+
+    #[test]
+    fn models_without_a_scene_graph_do_not_overlap() {
+        let mut children = model([2, 1, 1], &[[0, 0, 0, 1], [1, 0, 0, 1]]);
+        children.extend(model([2, 1, 1], &[[0, 0, 0, 1], [1, 0, 0, 1]]));
+        let scene = parse(&vox(&children)).unwrap();
+        let xs: Vec<i32> = world_voxels(&scene).iter().map(|p| p.x).collect();
+        assert_eq!(xs, [0, 1, 3, 4]);
+    }
+
+    #[test]
+    fn broken_files_are_errors() {
+        let bytes = vox(&model([2, 2, 2], &[[1, 1, 1, 1]]));
+        for len in 0..bytes.len() {
+            assert!(parse(&bytes[..len]).is_err(), "prefix of {len} bytes");
+        }
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[3] = b'!';
+        assert!(matches!(parse(&bad_magic), Err(VoxError::NotVox)));
+
+        let outside = vox(&model([2, 2, 2], &[[1, 2, 1, 1]]));
+        assert!(matches!(parse(&outside), Err(VoxError::Invalid(_))));
+    }
+}